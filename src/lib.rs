@@ -1,31 +1,1399 @@
 extern crate pest;
 #[macro_use]
 extern crate pest_derive;
+#[cfg(feature = "ndarray")]
+extern crate ndarray;
 
 use pest::Parser;
 use pest::iterators::Pairs;
 
-use std::io::{self, Write};
+mod math;
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::mem;
 
 #[cfg(debug_assertions)]
-const _GRAMMAR: &'static str = include_str!("bvh.pest");
+const _GRAMMAR: &str = include_str!("bvh.pest");
+
+// Step that values are rounded to before hashing in `Bvh::content_hash`
+const CONTENT_HASH_QUANTUM: f64 = 1e-3;
+
+// Lowest autocorrelation at which `Bvh::detect_period` considers a clip periodic, and how far below the best lag a shorter one may be
+const PERIOD_MIN_CORRELATION: f64 = 0.8;
+const PERIOD_TOLERANCE: f64 = 0.05;
+
+// Indent that starts each continuation line when `SerializeOptions::max_line_width` wraps a frame
+const FRAME_CONTINUATION_INDENT: &str = "  ";
 
 #[derive(Parser)]
 #[grammar = "bvh.pest"]
 struct BvhParser;
 
 #[derive(Debug)]
+pub enum BvhError {
+    Parse(String),
+    BadOffset { joint: String, found: usize },
+    ChannelCountMismatch { expected: usize, found: usize },
+    JointCountMismatch { expected: usize, found: usize },
+    UnsupportedChannels { joint: String },
+    UnknownJoint(String),
+    NestedJoints { ancestor: String, descendant: String },
+    FrameCountMismatch { declared: u32, found: u32 },
+    TakeChannelMismatch { take: usize, expected: usize, found: usize },
+    MissingPose(String),
+    PoseChannelMismatch { joint: String },
+    InvalidPermutation { columns: usize },
+    TopologyMismatch { joint: String },
+    RoundTripMismatch(String),
+}
+
+impl fmt::Display for BvhError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BvhError::Parse(ref message) => write!(f, "Couldn't parse BVH: {}", message),
+            BvhError::BadOffset { ref joint, found } => write!(f, "OFFSET for {} has {} components, expected 3", joint, found),
+            BvhError::ChannelCountMismatch { expected, found } => write!(f, "Expected {} channels per frame, found {}", expected, found),
+            BvhError::JointCountMismatch { expected, found } => write!(f, "Expected {} joints, found {}", expected, found),
+            BvhError::UnsupportedChannels { ref joint } => write!(f, "Channels of {} aren't supported by this operation", joint),
+            BvhError::UnknownJoint(ref joint) => write!(f, "No joint named {}", joint),
+            BvhError::NestedJoints { ref ancestor, ref descendant } => write!(f, "{} is an ancestor of {}", ancestor, descendant),
+            BvhError::FrameCountMismatch { declared, found } => write!(f, "Frames: declares {} frames but only {} are present", declared, found),
+            BvhError::TakeChannelMismatch { take, expected, found } => write!(f, "Take {} has {} channels per frame, expected {}", take, found, expected),
+            BvhError::MissingPose(ref joint) => write!(f, "No pose given for {}", joint),
+            BvhError::PoseChannelMismatch { ref joint } => write!(f, "Pose for {} doesn't match its channels", joint),
+            BvhError::InvalidPermutation { columns } => write!(f, "Column permutation must list each of the {} columns exactly once", columns),
+            BvhError::TopologyMismatch { ref joint } => write!(f, "Hierarchies differ at {}", joint),
+            BvhError::RoundTripMismatch(ref difference) => write!(f, "Serialized BVH doesn't parse back identically: {}", difference),
+        }
+    }
+}
+
+impl Error for BvhError {}
+
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    /// Accept common deviations from the format instead of rejecting them:
+    ///
+    /// - an OFFSET with missing components, which are padded with 0
+    /// - a Frame Time written as `30fps`
+    /// - a frame count larger than the frame data
+    /// - frame values separated by commas, which must appear between every two values of a frame, with the values filling exactly
+    ///   the declared number of frames (decimals must still use a point)
+    pub lenient: bool,
+    /// Extra channel names (e.g. `rotX`) and the channels they stand for, checked before the standard names.
+    pub channel_aliases: HashMap<String, Channel>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SerializeOptions {
+    /// How frame values that are whole numbers are written.
+    pub integer_style: IntegerStyle,
+    /// Wrap frame lines before they get longer than this many characters, continuing the frame on indented lines. Lines can only
+    /// break between values, so a value too wide to fit on a line of its own still overflows. `None` keeps each frame on one line.
+    pub max_line_width: Option<usize>,
+    /// End each frame with a `# t=<seconds>` comment giving its time from the start of the clip. Comments are skipped when parsing,
+    /// and don't count toward `max_line_width`.
+    pub timestamp_comments: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IntegerStyle {
+    /// Without a decimal point, e.g. `10`.
+    #[default]
+    Bare,
+    /// With a decimal point, e.g. `10.0`.
+    Decimal,
+}
+
+#[derive(Debug, Clone)]
 pub struct Bvh {
     pub hierarchy: Hierarchy,
     pub motion: Motion,
 }
 
-#[derive(Debug)]
+impl Bvh {
+    /// A clip holding `frame` for `num_frames` frames.
+    pub fn from_pose(hierarchy: Hierarchy, frame: &[f64], num_frames: usize, frame_time: f64) -> Result<Bvh, BvhError> {
+        let channels_per_frame = hierarchy.root.total_channels() as usize;
+        if frame.len() != channels_per_frame {
+            return Err(BvhError::ChannelCountMismatch {
+                expected: channels_per_frame,
+                found: frame.len(),
+            });
+        }
+        Ok(Bvh {
+            hierarchy,
+            motion: Motion {
+                num_frames: num_frames as u32,
+                frame_time,
+                frames: vec![frame.to_vec(); num_frames],
+            },
+        })
+    }
+
+    /// Names of joints whose rotation channels have more high-frequency energy (sum of absolute second differences over all frames) than `threshold`.
+    pub fn noisy_joints(&self, threshold: f64) -> Vec<String> {
+        let joints = self.hierarchy.joints();
+        let channel_offsets = self.hierarchy.channel_offsets();
+        joints.iter().zip(channel_offsets).filter(|&(joint, channel_offset)| {
+            let energy: f64 = joint.channels.iter().enumerate().filter(|&(_, channel)| channel.is_rotation()).map(|(index, _)| {
+                let column = channel_offset + index;
+                self.motion.frames.windows(3).map(|frames| (frames[2][column] - 2.0 * frames[1][column] + frames[0][column]).abs()).sum::<f64>()
+            }).sum();
+            energy > threshold
+        }).map(|(joint, _)| joint.name.clone()).collect()
+    }
+
+    /// World transforms of every joint (in `Hierarchy::joints` order) for every frame.
+    pub fn all_world_transforms(&self) -> Vec<Vec<[[f64; 4]; 4]>> {
+        self.motion.frames.iter().map(|frame| self.hierarchy.world_transforms(frame)).collect()
+    }
+
+    /// Local rotation of every joint (in `Hierarchy::joints` order) for every frame, indexed `[frame][joint]`. This is the rotation part of
+    /// `Joint::local_matrix`.
+    pub fn all_local_rotation_matrices(&self) -> Vec<Vec<[[f64; 3]; 3]>> {
+        let joints = self.hierarchy.joints();
+        let channel_offsets = self.hierarchy.channel_offsets();
+        self.motion.frames.iter().map(|frame| {
+            joints.iter().zip(channel_offsets.iter()).map(|(joint, &channel_offset)| {
+                joint.channels.iter().zip(frame[channel_offset..].iter()).filter(|&(channel, _)| channel.is_rotation()).fold(math::matrix3_identity(), |rotation, (channel, &value)| {
+                    math::matrix3_mul(&rotation, &math::matrix3_rotation(channel.axis(), value))
+                })
+            }).collect()
+        }).collect()
+    }
+
+    /// World positions of every joint (in `Hierarchy::joints` order) for every frame, indexed `[frame][joint]`.
+    pub fn to_position_sequence(&self) -> Vec<Vec<[f64; 3]>> {
+        self.all_world_transforms().iter().map(|transforms| transforms.iter().map(math::matrix4_translation).collect()).collect()
+    }
+
+    /// World-space velocity of every joint (in `Hierarchy::joints` order) for every frame, in units per second.
+    /// Uses central differences between neighbouring frames, and one-sided differences for the first and last frames.
+    pub fn joint_velocities(&self) -> Vec<Vec<[f64; 3]>> {
+        let positions = self.to_position_sequence();
+        let num_frames = positions.len();
+        (0..num_frames).map(|frame| {
+            let previous = frame.saturating_sub(1);
+            let next = (frame + 1).min(num_frames - 1);
+            let elapsed = (next - previous) as f64 * self.motion.frame_time;
+            (0..positions[frame].len()).map(|joint| {
+                if elapsed > 0.0 {
+                    math::scale(&math::sub(&positions[next][joint], &positions[previous][joint]), 1.0 / elapsed)
+                } else {
+                    [0.0; 3]
+                }
+            }).collect()
+        }).collect()
+    }
+
+    /// World-space acceleration of every joint (in `Hierarchy::joints` order) for every frame, in units per second squared.
+    /// Uses second differences between neighbouring frames; the first and last frames repeat their neighbour's value.
+    pub fn joint_accelerations(&self) -> Vec<Vec<[f64; 3]>> {
+        let positions = self.to_position_sequence();
+        let num_frames = positions.len();
+        let frame_time_squared = self.motion.frame_time * self.motion.frame_time;
+        (0..num_frames).map(|frame| {
+            (0..positions[frame].len()).map(|joint| {
+                if num_frames < 3 || frame_time_squared <= 0.0 {
+                    return [0.0; 3];
+                }
+                let middle = frame.max(1).min(num_frames - 2);
+                let first_difference = math::sub(&positions[middle + 1][joint], &positions[middle][joint]);
+                let second_difference = math::sub(&first_difference, &math::sub(&positions[middle][joint], &positions[middle - 1][joint]));
+                math::scale(&second_difference, 1.0 / frame_time_squared)
+            }).collect()
+        }).collect()
+    }
+
+    /// Removes leading and trailing frames in which no joint moves more than `epsilon` away from its position in the first/last active frame.
+    /// A clip that never moves is reduced to its first frame.
+    pub fn trim_static_ends(&mut self, epsilon: f64) {
+        let positions = self.to_position_sequence();
+        if positions.is_empty() {
+            return;
+        }
+        let moved = |frame: usize, reference: usize| positions[frame].iter().zip(positions[reference].iter()).any(|(a, b)| math::length(&math::sub(a, b)) > epsilon);
+        let last = positions.len() - 1;
+        let (start, end) = match (changed_span(positions.len(), |frame| moved(frame, 0)), changed_span(positions.len(), |frame| moved(frame, last))) {
+            (Some((first_moving, _)), Some((_, last_moving))) => (first_moving.saturating_sub(1), last_moving + 1),
+            _ => (0, 0),
+        };
+        self.motion.frames.truncate(end + 1);
+        self.motion.frames.drain(..start);
+        self.motion.num_frames = self.motion.frames.len() as u32;
+    }
+
+    /// First and last frame indices at which any channel differs from the first frame by more than `epsilon`. A clip that never
+    /// changes (or has no frames) gives `(0, 0)`.
+    pub fn active_range(&self, epsilon: f64) -> (usize, usize) {
+        let frames = &self.motion.frames;
+        changed_span(frames.len(), |frame| frames[frame].iter().zip(frames[0].iter()).any(|(a, b)| (a - b).abs() > epsilon)).unwrap_or((0, 0))
+    }
+
+    /// Rough count of distinct poses in the clip: frames are scanned in order and a frame counts as new if, for every pose counted so
+    /// far, some channel differs from it by more than `epsilon`. A clip cycling between a few poses scores low however long it is.
+    pub fn complexity_score(&self, epsilon: f64) -> usize {
+        let mut distinct: Vec<&Vec<f64>> = Vec::new();
+        for frame in self.motion.frames.iter() {
+            if distinct.iter().all(|pose| pose.iter().zip(frame.iter()).any(|(a, b)| (a - b).abs() > epsilon)) {
+                distinct.push(frame);
+            }
+        }
+        distinct.len()
+    }
+
+    /// Copy of the clip cut down to `target_frames` evenly spaced frames (always including the first and last), for quick previews. The
+    /// frame time is stretched to keep the clip's span. Clips that already have no more than `target_frames` frames are copied as-is, and
+    /// a target below 2 keeps just the first frame.
+    pub fn thumbnail(&self, target_frames: usize) -> Bvh {
+        let num_frames = self.motion.frames.len();
+        if num_frames <= target_frames.max(1) {
+            return self.clone();
+        }
+        let last = num_frames - 1;
+        let (frames, frame_time) = if target_frames < 2 {
+            (vec![self.motion.frames[0].clone()], self.motion.frame_time)
+        } else {
+            let step = last as f64 / (target_frames - 1) as f64;
+            ((0..target_frames).map(|index| self.motion.frames[(index as f64 * step).round() as usize].clone()).collect(), self.motion.frame_time * step)
+        };
+        Bvh {
+            hierarchy: self.hierarchy.clone(),
+            motion: Motion {
+                num_frames: frames.len() as u32,
+                frame_time,
+                frames,
+            },
+        }
+    }
+
+    /// Pose at `phase` through the clip, where 0 is the first frame and phases approaching 1 reach the last. Phase wraps modulo 1, so looping clips can be driven by an ever-increasing value.
+    pub fn pose_at_phase(&self, phase: f64) -> Vec<f64> {
+        let span = self.motion.frames.len().saturating_sub(1) as f64 * self.motion.frame_time;
+        self.motion.sample(phase.rem_euclid(1.0) * span)
+    }
+
+    /// World transform of `joint` at `time` seconds, using `Motion::sample` to interpolate between frames.
+    /// Returns `None` if there's no such joint or no frames.
+    pub fn joint_transform_at(&self, joint: &str, time: f64) -> Option<[[f64; 4]; 4]> {
+        let joint_index = self.hierarchy.joint_index(joint)?;
+        if self.motion.frames.is_empty() {
+            return None;
+        }
+        Some(self.hierarchy.world_transforms(&self.motion.sample(time))[joint_index])
+    }
+
+    /// For every frame and joint (in `Hierarchy::joints` order), the joint's local rotation relative to its rest orientation, as a rotation
+    /// axis scaled by the angle in degrees. Rest orientations are the identity in BVH, so a joint with zeroed rotation channels gives zero.
+    pub fn local_rotations_relative_to_rest(&self) -> Vec<Vec<[f64; 3]>> {
+        let joints = self.hierarchy.joints();
+        let channel_offsets = self.hierarchy.channel_offsets();
+        self.motion.frames.iter().map(|frame| {
+            joints.iter().zip(channel_offsets.iter()).map(|(joint, &channel_offset)| {
+                let local = joint.local_matrix(&frame[channel_offset..channel_offset + joint.channels.len()]);
+                math::quaternion_to_rotation_vector(&math::matrix3_to_quaternion(&math::matrix4_rotation(&local)))
+            }).collect()
+        }).collect()
+    }
+
+    /// The root's world orientation in every frame as a quaternion (`[x, y, z, w]`, with w non-negative). The root has no parent, so
+    /// this is just the rotation from its own rotation channels.
+    pub fn root_world_quat(&self) -> Vec<[f64; 4]> {
+        let root = &self.hierarchy.root;
+        self.motion.frames.iter().map(|frame| math::matrix3_to_quaternion(&math::matrix4_rotation(&root.local_matrix(&frame[..root.channels.len()])))).collect()
+    }
+
+    /// Every joint's local rotation relative to its bind (rest) orientation as a quaternion (`[x, y, z, w]`, with w non-negative) per
+    /// frame. Rest orientations are the identity in BVH, so joints that stay at rest give the identity quaternion in every frame.
+    pub fn rotation_deltas_from_bind(&self) -> Vec<(String, Vec<[f64; 4]>)> {
+        let rotations = self.all_local_rotation_matrices();
+        self.hierarchy.joints().iter().enumerate().map(|(index, joint)| {
+            (joint.name.clone(), rotations.iter().map(|frame_rotations| math::matrix3_to_quaternion(&frame_rotations[index])).collect())
+        }).collect()
+    }
+
+    /// Splits `joint`'s local rotation in every frame into a twist about `twist_axis` (in the joint's space) and the remaining swing,
+    /// such that rotation = swing * twist. Returns the twist angle in degrees and the swing quaternion (`[x, y, z, w]`) per frame,
+    /// or `None` if there's no such joint or `twist_axis` is zero.
+    pub fn twist_swing(&self, joint: &str, twist_axis: [f64; 3]) -> Option<Vec<(f64, [f64; 4])>> {
+        let joint_index = self.hierarchy.joint_index(joint)?;
+        let joint = self.hierarchy.joints()[joint_index];
+        let channel_offset = self.hierarchy.channel_offsets()[joint_index];
+        let axis = math::normalize(&twist_axis);
+        if math::length(&axis) == 0.0 {
+            return None;
+        }
+        Some(self.motion.frames.iter().map(|frame| {
+            let local = joint.local_matrix(&frame[channel_offset..channel_offset + joint.channels.len()]);
+            let rotation = math::matrix3_to_quaternion(&math::matrix4_rotation(&local));
+            let projection = math::dot(&[rotation[0], rotation[1], rotation[2]], &axis);
+            let twist_length = projection.hypot(rotation[3]);
+            let twist = if twist_length > 1e-12 {
+                [axis[0] * projection / twist_length, axis[1] * projection / twist_length, axis[2] * projection / twist_length, rotation[3] / twist_length]
+            } else {
+                // A half-turn swing perpendicular to the axis leaves the twist undefined
+                [0.0, 0.0, 0.0, 1.0]
+            };
+            let swing = math::quaternion_mul(&rotation, &math::quaternion_conjugate(&twist));
+            let twist_angle = 2.0 * math::dot(&[twist[0], twist[1], twist[2]], &axis).atan2(twist[3]);
+            (twist_angle.to_degrees(), swing)
+        }).collect())
+    }
+
+    /// Angle in degrees at joint `b` between the directions to joints `a` and `c`, for every frame. Returns `None` if any joint doesn't exist.
+    pub fn joint_angle(&self, a: &str, b: &str, c: &str) -> Option<Vec<f64>> {
+        let (a, b, c) = (self.hierarchy.joint_index(a)?, self.hierarchy.joint_index(b)?, self.hierarchy.joint_index(c)?);
+        Some(self.to_position_sequence().iter().map(|positions| {
+            let to_a = math::sub(&positions[a], &positions[b]);
+            let to_c = math::sub(&positions[c], &positions[b]);
+            let lengths = math::length(&to_a) * math::length(&to_c);
+            if lengths > 0.0 {
+                (math::dot(&to_a, &to_c) / lengths).clamp(-1.0, 1.0).acos().to_degrees()
+            } else {
+                0.0
+            }
+        }).collect())
+    }
+
+    /// `joint`'s local +Z axis in world space for every frame, which is the forward direction for most skeletons. Returns nothing if
+    /// there's no such joint.
+    pub fn facing_directions(&self, joint: &str) -> Vec<[f64; 3]> {
+        let joint_index = match self.hierarchy.joint_index(joint) {
+            Some(joint_index) => joint_index,
+            None => return Vec::new(),
+        };
+        self.all_world_transforms().iter().map(|transforms| {
+            let rotation = math::matrix4_rotation(&transforms[joint_index]);
+            [rotation[0][2], rotation[1][2], rotation[2][2]]
+        }).collect()
+    }
+
+    /// The joint whose world position covers the most ground over the clip, measured as the diagonal of the bounding box of its
+    /// trajectory, together with that diagonal. Returns `None` if there are no frames.
+    pub fn most_mobile_joint(&self) -> Option<(String, f64)> {
+        let positions = self.to_position_sequence();
+        if positions.is_empty() {
+            return None;
+        }
+        self.hierarchy.joints().iter().enumerate().map(|(index, joint)| {
+            let mut min = [f64::INFINITY; 3];
+            let mut max = [f64::NEG_INFINITY; 3];
+            for position in positions.iter().map(|frame_positions| frame_positions[index]) {
+                for axis in 0..3 {
+                    min[axis] = min[axis].min(position[axis]);
+                    max[axis] = max[axis].max(position[axis]);
+                }
+            }
+            (joint.name.clone(), math::length(&math::sub(&max, &min)))
+        }).fold(None, |best: Option<(String, f64)>, (name, extent)| match best {
+            Some((_, best_extent)) if best_extent >= extent => best,
+            _ => Some((name, extent)),
+        })
+    }
+
+    /// Indices of frames where `joint`'s world-space speed exceeds `speed_threshold`. Returns nothing if there's no such joint.
+    pub fn fast_motion_frames(&self, joint: &str, speed_threshold: f64) -> Vec<usize> {
+        let joint_index = match self.hierarchy.joint_index(joint) {
+            Some(joint_index) => joint_index,
+            None => return Vec::new(),
+        };
+        self.joint_velocities().iter().enumerate().filter(|&(_, velocities)| math::length(&velocities[joint_index]) > speed_threshold).map(|(frame, _)| frame).collect()
+    }
+
+    /// Swaps the subtrees rooted at joints `a` and `b` (see `Hierarchy::swap_subtrees`), moving their channel columns in every frame along with them.
+    pub fn swap_subtrees(&mut self, a: &str, b: &str) -> Result<(), BvhError> {
+        let columns = (self.hierarchy.subtree_columns(a), self.hierarchy.subtree_columns(b));
+        self.hierarchy.swap_subtrees(a, b)?;
+        if let (Some(a_columns), Some(b_columns)) = columns {
+            if a_columns != b_columns {
+                let (first, second) = if a_columns.0 < b_columns.0 { (a_columns, b_columns) } else { (b_columns, a_columns) };
+                let channels_per_frame = self.hierarchy.root.total_channels() as usize;
+                let permutation: Vec<usize> = (0..first.0).chain(second.0..second.1).chain(first.1..second.0).chain(first.0..first.1).chain(second.1..channels_per_frame).collect();
+                self.motion.reorder_columns(&permutation)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Rebuilds the skeleton so that `new_root` is the root, reversing the parent/child links on the path from the old root.
+    /// World-space motion is preserved exactly: every joint on that path gets position channels plus three rotation channels
+    /// (keeping its existing rotation order if it has one), since a reversed bone generally needs both to follow the original pose.
+    /// Joints elsewhere in the tree are unchanged. If `new_root` had an end site it's dropped, as a joint can't have both.
+    pub fn reroot(&self, new_root: &str) -> Result<Bvh, BvhError> {
+        let joints = self.hierarchy.joints();
+        let parents = self.hierarchy.parent_indices();
+        let channel_offsets = self.hierarchy.channel_offsets();
+        let new_root_index = self.hierarchy.joint_index(new_root).ok_or_else(|| BvhError::UnknownJoint(new_root.into()))?;
+        let rest_positions = self.hierarchy.rest_positions();
+
+        let mut plan = Vec::new();
+        let root = build_rerooted_joint(&joints, &parents, &channel_offsets, new_root_index, None, rest_positions[new_root_index], &mut plan);
+
+        let frames = self.all_world_transforms().iter().zip(self.motion.frames.iter()).map(|(transforms, frame)| {
+            let mut new_frame = Vec::with_capacity(frame.len());
+            for channels in plan.iter() {
+                match *channels {
+                    RerootedChannels::Copied { start, end } => new_frame.extend_from_slice(&frame[start..end]),
+                    RerootedChannels::Solved { index, parent, offset, rotation_axes } => {
+                        let (parent_rotation, parent_position) = match parent {
+                            Some(parent) => (math::matrix4_rotation(&transforms[parent]), math::matrix4_translation(&transforms[parent])),
+                            None => (math::matrix3_identity(), [0.0; 3]),
+                        };
+                        let parent_inverse = math::matrix3_transpose(&parent_rotation);
+                        let translation = math::matrix3_mul_vector(&parent_inverse, &math::sub(&math::matrix4_translation(&transforms[index]), &parent_position));
+                        new_frame.extend_from_slice(&math::sub(&translation, &offset));
+                        let rotation = math::matrix3_mul(&parent_inverse, &math::matrix4_rotation(&transforms[index]));
+                        new_frame.extend_from_slice(&math::matrix3_to_euler(&rotation, rotation_axes));
+                    }
+                }
+            }
+            new_frame
+        }).collect();
+
+        Ok(Bvh {
+            hierarchy: Hierarchy {
+                root,
+            },
+            motion: Motion {
+                num_frames: self.motion.num_frames,
+                frame_time: self.motion.frame_time,
+                frames,
+            },
+        })
+    }
+
+    /// Convex hull, in counter-clockwise order on the ground (XZ) plane, of every joint's world position over all frames.
+    pub fn ground_footprint(&self) -> Vec<[f64; 2]> {
+        let points = self.to_position_sequence().iter().flat_map(|positions| positions.iter().map(|position| [position[0], position[2]]).collect::<Vec<[f64; 2]>>()).collect();
+        math::convex_hull_2d(points)
+    }
+
+    /// Applies `transform` to the root in every frame by rewriting its position and rotation channels, so the change is stored in the frame data.
+    /// The transform's upper-left 3x3 must be a rotation (scale can't be stored in channels), and the root needs all three position channels
+    /// and three distinct rotation channels.
+    pub fn bake_global_transform(&mut self, transform: [[f64; 4]; 4]) -> Result<(), BvhError> {
+        let root = &self.hierarchy.root;
+        let rotation_axes = root.rotation_axes();
+        let has_positions = (0..3).all(|axis| root.channels.iter().any(|channel| channel.is_position() && channel.axis() == axis));
+        let rotation_axes = match rotation_axes {
+            Some(rotation_axes) if has_positions => rotation_axes,
+            _ => {
+                return Err(BvhError::UnsupportedChannels {
+                    joint: root.name.clone(),
+                })
+            }
+        };
+
+        let offset = root.offset.to_array();
+        for frame in self.motion.frames.iter_mut() {
+            let local = root.local_matrix(&frame[..root.channels.len()]);
+            let baked = math::matrix4_mul(&transform, &local);
+            let translation = math::sub(&math::matrix4_translation(&baked), &offset);
+            let euler = math::matrix3_to_euler(&math::matrix4_rotation(&baked), rotation_axes);
+            let mut rotation_index = 0;
+            for (channel, value) in root.channels.iter().zip(frame.iter_mut()) {
+                if channel.is_position() {
+                    *value = translation[channel.axis()];
+                } else {
+                    *value = euler[rotation_index];
+                    rotation_index += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Range (max - min) of every channel's values over all frames, in frame layout order.
+    pub fn channel_ranges(&self) -> Vec<(String, Channel, f64)> {
+        let mut column = 0;
+        let mut ranges = Vec::new();
+        for joint in self.hierarchy.joints() {
+            for &channel in joint.channels.iter() {
+                let values = self.motion.frames.iter().map(|frame| frame[column]);
+                let (min, max) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), value| (min.min(value), max.max(value)));
+                ranges.push((joint.name.clone(), channel, if max >= min { max - min } else { 0.0 }));
+                column += 1;
+            }
+        }
+        ranges
+    }
+
+    /// Splits `total_bits` between the rotation channels (in frame layout order) in proportion to their ranges (see `channel_ranges`),
+    /// so channels that move more get more precision. Leftover bits from rounding go to the channels with the largest remainders, so the
+    /// shares always add up to `total_bits`. If no rotation channel moves, the bits are split evenly.
+    pub fn rotation_budget(&self, total_bits: usize) -> Vec<(String, Channel, usize)> {
+        let ranges: Vec<(String, Channel, f64)> = self.channel_ranges().into_iter().filter(|&(_, channel, _)| channel.is_rotation()).collect();
+        if ranges.is_empty() {
+            return Vec::new();
+        }
+        let total_range: f64 = ranges.iter().map(|&(_, _, range)| range).sum();
+        let weight = |range: f64| if total_range > 0.0 { range / total_range } else { 1.0 / ranges.len() as f64 };
+        let shares: Vec<f64> = ranges.iter().map(|&(_, _, range)| weight(range) * total_bits as f64).collect();
+        let mut bits: Vec<usize> = shares.iter().map(|share| share.floor() as usize).collect();
+        let mut by_remainder: Vec<usize> = (0..shares.len()).collect();
+        by_remainder.sort_by(|&a, &b| (shares[b] - shares[b].floor()).partial_cmp(&(shares[a] - shares[a].floor())).unwrap_or(::std::cmp::Ordering::Equal));
+        let assigned: usize = bits.iter().sum();
+        for &index in by_remainder.iter().cycle().take(total_bits.saturating_sub(assigned)) {
+            bits[index] += 1;
+        }
+        ranges.into_iter().zip(bits).map(|((name, channel, _), bits)| (name, channel, bits)).collect()
+    }
+
+    /// Hash of the hierarchy structure together with offsets, frame time and frame data rounded to a fixed step (1e-3), so clips that
+    /// differ only by tiny amounts usually hash the same (values straddling a rounding boundary can still differ).
+    /// This is 64-bit FNV-1a over a fixed little-endian encoding of those values, so stored hashes stay valid across builds and platforms.
+    pub fn content_hash(&self) -> u64 {
+        let quantize = |value: f64| ((value / CONTENT_HASH_QUANTUM).round() as i64).to_le_bytes();
+        let mut hasher = Fnv1a::new();
+        for (joint, parent) in self.hierarchy.joints().iter().zip(self.hierarchy.parent_indices()) {
+            hasher.write(&(joint.name.len() as u64).to_le_bytes());
+            hasher.write(joint.name.as_bytes());
+            hasher.write(&parent.map_or(u64::MAX, |parent| parent as u64).to_le_bytes());
+            hasher.write(&(joint.channels.len() as u64).to_le_bytes());
+            for channel in joint.channels.iter() {
+                hasher.write(&[if channel.is_position() { 0 } else { 3 } + channel.axis() as u8]);
+            }
+            for &component in joint.offset.to_array().iter() {
+                hasher.write(&quantize(component));
+            }
+            if let JointChildren::EndSite(ref end_site) = joint.children {
+                for &component in end_site.offset.to_array().iter() {
+                    hasher.write(&quantize(component));
+                }
+            }
+        }
+        hasher.write(&quantize(self.motion.frame_time));
+        hasher.write(&(self.motion.frames.len() as u64).to_le_bytes());
+        for &value in self.motion.frames.iter().flat_map(|frame| frame.iter()) {
+            hasher.write(&quantize(value));
+        }
+        hasher.finish()
+    }
+
+    /// Unit vector along which the root travels: the net displacement from the first frame to the last, or, for paths that end
+    /// close to where they started (net displacement under a quarter of the path's extent), the principal axis of the root positions.
+    /// Returns zero if the root doesn't move.
+    pub fn dominant_travel_direction(&self) -> [f64; 3] {
+        let positions: Vec<[f64; 3]> = self.motion.frames.iter().map(|frame| self.hierarchy.world_positions(frame)[0]).collect();
+        let (first, last) = match (positions.first(), positions.last()) {
+            (Some(first), Some(last)) => (*first, *last),
+            _ => return [0.0; 3],
+        };
+        let net = math::sub(&last, &first);
+        let mut min = [f64::INFINITY; 3];
+        let mut max = [f64::NEG_INFINITY; 3];
+        for position in positions.iter() {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(position[axis]);
+                max[axis] = max[axis].max(position[axis]);
+            }
+        }
+        let extent = math::length(&math::sub(&max, &min));
+        if extent < 1e-12 {
+            return [0.0; 3];
+        }
+        if math::length(&net) >= 0.25 * extent {
+            return math::normalize(&net);
+        }
+
+        let mean = math::scale(&positions.iter().fold([0.0; 3], |sum, position| math::add(&sum, position)), 1.0 / positions.len() as f64);
+        let mut covariance = [[0.0; 3]; 3];
+        for position in positions.iter() {
+            let d = math::sub(position, &mean);
+            for row in 0..3 {
+                for column in 0..3 {
+                    covariance[row][column] += d[row] * d[column];
+                }
+            }
+        }
+        let mut direction = *covariance.iter().max_by(|a, b| math::length(a).partial_cmp(&math::length(b)).unwrap_or(::std::cmp::Ordering::Equal)).unwrap();
+        for _ in 0..64 {
+            direction = math::normalize(&math::matrix3_mul_vector(&covariance, &direction));
+        }
+        if math::dot(&direction, &net) < 0.0 {
+            direction = math::scale(&direction, -1.0);
+        }
+        direction
+    }
+
+    /// The root's world position in every frame with the `up_axis` component dropped, leaving the other two in x, y, z order (e.g.
+    /// `[x, z]` for Y up), which traces the path walked across the ground.
+    pub fn root_ground_path(&self, up_axis: Axis) -> Vec<[f64; 2]> {
+        let up = up_axis.index();
+        let (first, second) = ((up + 1) % 3, (up + 2) % 3);
+        let (first, second) = (first.min(second), first.max(second));
+        self.motion.frames.iter().map(|frame| {
+            let position = self.hierarchy.world_positions(frame)[0];
+            [position[first], position[second]]
+        }).collect()
+    }
+
+    /// Writes the motion in a compact little-endian binary form: joint count, channels per frame and frame count as `u32`s,
+    /// the frame time as an `f64`, then every channel value as an `f32`. The hierarchy isn't included; see `read_binary`.
+    pub fn write_binary<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&(self.hierarchy.joints().len() as u32).to_le_bytes())?;
+        w.write_all(&self.hierarchy.root.total_channels().to_le_bytes())?;
+        w.write_all(&(self.motion.frames.len() as u32).to_le_bytes())?;
+        w.write_all(&self.motion.frame_time.to_le_bytes())?;
+        for &value in self.motion.frames.iter().flat_map(|frame| frame.iter()) {
+            w.write_all(&(value as f32).to_le_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads motion written by `write_binary`, which must have been written for a hierarchy with the same joint and channel counts.
+    pub fn read_binary<R: Read>(hierarchy: Hierarchy, r: &mut R) -> io::Result<Bvh> {
+        let mut u32_bytes = [0; 4];
+        let mut read_u32 = |r: &mut R| r.read_exact(&mut u32_bytes).map(|_| u32::from_le_bytes(u32_bytes));
+        let num_joints = read_u32(r)?;
+        let channels_per_frame = read_u32(r)?;
+        let num_frames = read_u32(r)?;
+        let mut f64_bytes = [0; 8];
+        r.read_exact(&mut f64_bytes)?;
+        let frame_time = f64::from_le_bytes(f64_bytes);
+        if num_joints as usize != hierarchy.joints().len() || channels_per_frame != hierarchy.root.total_channels() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Binary motion doesn't match the hierarchy"));
+        }
+
+        let mut frames = Vec::new();
+        let mut f32_bytes = [0; 4];
+        for _ in 0..num_frames {
+            let mut frame = Vec::with_capacity(channels_per_frame as usize);
+            for _ in 0..channels_per_frame {
+                r.read_exact(&mut f32_bytes)?;
+                frame.push(f32::from_le_bytes(f32_bytes) as f64);
+            }
+            frames.push(frame);
+        }
+
+        Ok(Bvh {
+            hierarchy,
+            motion: Motion {
+                num_frames,
+                frame_time,
+                frames,
+            },
+        })
+    }
+
+    /// Lowest world-space Y reached by any of `foot_joints` over the whole clip, as an estimate of the floor height. Unknown joints are
+    /// ignored; if none of them exist, or there are no frames, this is infinity.
+    pub fn estimate_ground_height(&self, foot_joints: &[&str]) -> f64 {
+        let indices: Vec<usize> = foot_joints.iter().filter_map(|joint| self.hierarchy.joint_index(joint)).collect();
+        self.to_position_sequence().iter().flat_map(|positions| indices.iter().map(move |&index| positions[index][1])).fold(f64::INFINITY, f64::min)
+    }
+
+    /// Shifts the root's Yposition channel in every frame so the lowest point reached by `foot_joints` (see `estimate_ground_height`)
+    /// sits at `floor_y`. Does nothing if the root has no Yposition channel or none of the joints exist.
+    pub fn ground_to_floor(&mut self, foot_joints: &[&str], floor_y: f64) {
+        let column = match self.hierarchy.root.channels.iter().position(|&channel| channel == Channel::YPosition) {
+            Some(column) => column,
+            None => return,
+        };
+        let height = self.estimate_ground_height(foot_joints);
+        if !height.is_finite() {
+            return;
+        }
+        for frame in self.motion.frames.iter_mut() {
+            frame[column] += floor_y - height;
+        }
+    }
+
+    /// Total horizontal (XZ) distance `foot_joint` slides while planted, where the foot counts as planted in a frame if it's within
+    /// `contact_threshold` of its lowest height over the clip (see `estimate_ground_height`). Only movement between two consecutive
+    /// planted frames is counted, so lower is better. Returns 0 if there's no such joint.
+    pub fn foot_slide(&self, foot_joint: &str, contact_threshold: f64) -> f64 {
+        let joint_index = match self.hierarchy.joint_index(foot_joint) {
+            Some(joint_index) => joint_index,
+            None => return 0.0,
+        };
+        let ground = self.estimate_ground_height(&[foot_joint]);
+        let positions: Vec<[f64; 3]> = self.to_position_sequence().iter().map(|positions| positions[joint_index]).collect();
+        positions.windows(2).filter(|pair| pair.iter().all(|position| position[1] - ground <= contact_threshold)).map(|pair| {
+            (pair[1][0] - pair[0][0]).hypot(pair[1][2] - pair[0][2])
+        }).sum()
+    }
+
+    /// Sum of every joint's squared world-space speed for each frame, as a rough measure of how energetic the motion is.
+    pub fn kinetic_energy_proxy(&self) -> Vec<f64> {
+        self.joint_velocities().iter().map(|velocities| velocities.iter().map(|velocity| math::dot(velocity, velocity)).sum()).collect()
+    }
+
+    /// Most likely loop length in frames, found by autocorrelating every channel over lags from `min_frames` to half the clip. The
+    /// shortest lag that correlates nearly as well as the best one is chosen so multiples of the period aren't returned. Returns `None`
+    /// if no lag correlates strongly enough for the clip to look periodic.
+    pub fn detect_period(&self, min_frames: usize) -> Option<usize> {
+        let frames = &self.motion.frames;
+        let channels_per_frame = self.hierarchy.root.total_channels() as usize;
+        let means: Vec<f64> = (0..channels_per_frame).map(|channel| frames.iter().map(|frame| frame[channel]).sum::<f64>() / frames.len() as f64).collect();
+        let centered: Vec<Vec<f64>> = frames.iter().map(|frame| frame.iter().zip(means.iter()).map(|(value, mean)| value - mean).collect()).collect();
+        let correlations: Vec<(usize, f64)> = (min_frames.max(1)..frames.len() / 2 + 1).filter_map(|lag| {
+            let (mut product, mut early, mut late) = (0.0, 0.0, 0.0);
+            for (a, b) in centered.iter().zip(centered[lag..].iter()) {
+                for (a, b) in a.iter().zip(b.iter()) {
+                    product += a * b;
+                    early += a * a;
+                    late += b * b;
+                }
+            }
+            let norm = (early * late).sqrt();
+            if norm > 0.0 { Some((lag, product / norm)) } else { None }
+        }).collect();
+        let best = correlations.iter().map(|&(_, correlation)| correlation).fold(f64::NEG_INFINITY, f64::max);
+        if best < PERIOD_MIN_CORRELATION {
+            return None;
+        }
+        correlations.iter().find(|&&(_, correlation)| correlation >= best - PERIOD_TOLERANCE).map(|&(lag, _)| lag)
+    }
+
+    /// Lag in frames by which `joint_b`'s `channel` trails `joint_a`'s, found by cross-correlating the two mean-centered signals over
+    /// lags up to half the clip either way. Positive means `joint_b` follows `joint_a`. Returns `None` if either joint doesn't exist or
+    /// lacks the channel, or if the signals never correlate (e.g. one is constant).
+    pub fn relative_phase(&self, joint_a: &str, joint_b: &str, channel: Channel) -> Option<f64> {
+        let column = |name: &str| {
+            let joint_index = self.hierarchy.joint_index(name)?;
+            let index = self.hierarchy.joints()[joint_index].channels.iter().position(|&joint_channel| joint_channel == channel)?;
+            Some(self.hierarchy.channel_offsets()[joint_index] + index)
+        };
+        let (column_a, column_b) = (column(joint_a)?, column(joint_b)?);
+        let centered = |column: usize| {
+            let values: Vec<f64> = self.motion.frames.iter().map(|frame| frame[column]).collect();
+            let mean = values.iter().sum::<f64>() / values.len().max(1) as f64;
+            values.iter().map(|value| value - mean).collect::<Vec<f64>>()
+        };
+        let (a, b) = (centered(column_a), centered(column_b));
+        let max_lag = (a.len() / 2) as isize;
+        let correlations = (-max_lag..max_lag + 1).filter_map(|lag| {
+            let (a, b) = if lag >= 0 { (&a[..a.len() - lag as usize], &b[lag as usize..]) } else { (&a[(-lag) as usize..], &b[..b.len() - (-lag) as usize]) };
+            let (mut product, mut a_energy, mut b_energy) = (0.0, 0.0, 0.0);
+            for (a, b) in a.iter().zip(b.iter()) {
+                product += a * b;
+                a_energy += a * a;
+                b_energy += b * b;
+            }
+            let norm = (a_energy * b_energy).sqrt();
+            if norm > 0.0 { Some((lag, product / norm)) } else { None }
+        });
+        correlations.fold(None, |best: Option<(isize, f64)>, (lag, correlation)| match best {
+            // Prefer the shortest lag when a periodic signal matches equally well a whole period away
+            Some((best_lag, best_correlation)) if best_correlation > correlation + 1e-9 || (best_correlation >= correlation - 1e-9 && best_lag.abs() <= lag.abs()) => best,
+            _ => Some((lag, correlation)),
+        }).map(|(lag, _)| lag as f64)
+    }
+
+    /// How far each frame is from being mirror-symmetric across the X = 0 plane: the sum over `pairs` of the absolute difference
+    /// between one joint's rotation and the mirror of the other's, where mirroring keeps the X rotation and negates Y and Z. Pairs
+    /// naming an unknown joint, or a joint without rotation channels, are ignored.
+    pub fn symmetry_error(&self, pairs: &[(&str, &str)]) -> Vec<f64> {
+        let pairs: Vec<(usize, usize)> = pairs.iter().filter_map(|&(a, b)| Some((self.hierarchy.joint_index(a)?, self.hierarchy.joint_index(b)?))).collect();
+        self.motion.frames.iter().map(|frame| {
+            let poses = self.hierarchy.decode_frame(frame).expect("frame width doesn't match the hierarchy");
+            pairs.iter().filter_map(|&(a, b)| {
+                let (a, b) = (poses[a].1.rotation?, poses[b].1.rotation?);
+                Some((a[0] - b[0]).abs() + (a[1] + b[1]).abs() + (a[2] + b[2]).abs())
+            }).sum()
+        }).collect()
+    }
+
+    /// Splits the motion into one containing only the position channels and one containing only the rotation channels, each keeping
+    /// the columns in their original order.
+    pub fn split_pos_rot(&self) -> (Motion, Motion) {
+        let layout = self.hierarchy.channel_layout();
+        let select = |keep: fn(&Channel) -> bool| Motion {
+            num_frames: self.motion.num_frames,
+            frame_time: self.motion.frame_time,
+            frames: self.motion.frames.iter().map(|frame| frame.iter().zip(layout.iter()).filter(|&(_, channel)| keep(channel)).map(|(&value, _)| value).collect()).collect(),
+        };
+        (select(Channel::is_position), select(Channel::is_rotation))
+    }
+
+    /// Total degrees `joint` turns about each of its rotation channels' axes (x, y, z) over the clip. Each frame-to-frame change is
+    /// unwrapped to the shortest turn, so a joint spinning twice reports about 720. Returns `None` if there's no such joint.
+    pub fn cumulative_rotation(&self, joint: &str) -> Option<[f64; 3]> {
+        let joint_index = self.hierarchy.joint_index(joint)?;
+        let channel_offset = self.hierarchy.channel_offsets()[joint_index];
+        let mut totals = [0.0; 3];
+        for (column, channel) in self.hierarchy.joints()[joint_index].channels.iter().enumerate().filter(|&(_, channel)| channel.is_rotation()) {
+            totals[channel.axis()] += self.motion.frames.windows(2).map(|pair| {
+                let change = pair[1][channel_offset + column] - pair[0][channel_offset + column];
+                (change + 180.0).rem_euclid(360.0) - 180.0
+            }).map(f64::abs).sum::<f64>();
+        }
+        Some(totals)
+    }
+
+    /// How much each ancestor of `effector` (from the root down) moves it through rotation: the distance the effector moves when that
+    /// ancestor's rotation channels are zeroed, averaged over all frames. Returns nothing if there's no such joint.
+    pub fn effector_contribution(&self, effector: &str) -> Vec<(String, f64)> {
+        let effector = match self.hierarchy.joint_index(effector) {
+            Some(effector) => effector,
+            None => return Vec::new(),
+        };
+        let joints = self.hierarchy.joints();
+        let parents = self.hierarchy.parent_indices();
+        let channel_offsets = self.hierarchy.channel_offsets();
+        let mut ancestors = Vec::new();
+        let mut current = parents[effector];
+        while let Some(index) = current {
+            ancestors.push(index);
+            current = parents[index];
+        }
+        ancestors.reverse();
+        let frame_count = self.motion.frames.len().max(1) as f64;
+        ancestors.iter().map(|&ancestor| {
+            let total: f64 = self.motion.frames.iter().map(|frame| {
+                let mut zeroed = frame.clone();
+                for (column, channel) in joints[ancestor].channels.iter().enumerate() {
+                    if channel.is_rotation() {
+                        zeroed[channel_offsets[ancestor] + column] = 0.0;
+                    }
+                }
+                let moved = self.hierarchy.world_positions(&zeroed)[effector];
+                math::length(&math::sub(&self.hierarchy.world_positions(frame)[effector], &moved))
+            }).sum();
+            (joints[ancestor].name.clone(), total / frame_count)
+        }).collect()
+    }
+
+    /// Every joint's position relative to its parent in `frame`: its offset plus its own position channels, without any parent
+    /// transform applied. The frame must have a value for every channel.
+    pub fn local_positions(&self, frame: &[f64]) -> Result<Vec<(String, [f64; 3])>, BvhError> {
+        Ok(self.hierarchy.joints().iter().zip(self.hierarchy.decode_frame(frame)?).map(|(joint, (name, pose))| {
+            (name, math::add(&joint.offset.to_array(), &pose.position.unwrap_or([0.0; 3])))
+        }).collect())
+    }
+
+    /// Blends the last `frames` frames toward `target` with a smoothstep curve so the clip comes to rest in that pose, ending exactly on
+    /// it. Rotations of joints with three distinct rotation channels are blended with slerp; every other channel is blended linearly.
+    pub fn ease_to_pose(&mut self, target: &[f64], frames: usize) -> Result<(), BvhError> {
+        let channels_per_frame = self.hierarchy.root.total_channels() as usize;
+        if target.len() != channels_per_frame {
+            return Err(BvhError::ChannelCountMismatch {
+                expected: channels_per_frame,
+                found: target.len(),
+            });
+        }
+        let frames = frames.min(self.motion.frames.len());
+        let start = self.motion.frames.len() - frames;
+        let joints = self.hierarchy.joints();
+        let channel_offsets = self.hierarchy.channel_offsets();
+        let rotation = |joint: &Joint, values: &[f64]| math::matrix3_to_quaternion(&math::matrix4_rotation(&joint.local_matrix(values)));
+        for (step, frame) in self.motion.frames[start..].iter_mut().enumerate() {
+            let t = (step + 1) as f64 / frames as f64;
+            let weight = t * t * (3.0 - 2.0 * t);
+            let mut eased: Vec<f64> = frame.iter().zip(target.iter()).map(|(a, b)| a + (b - a) * weight).collect();
+            for (joint, &channel_offset) in joints.iter().zip(channel_offsets.iter()) {
+                let rotation_axes = match joint.rotation_axes() {
+                    Some(rotation_axes) => rotation_axes,
+                    None => continue,
+                };
+                let columns = channel_offset..channel_offset + joint.channels.len();
+                let blended = math::quaternion_slerp(&rotation(joint, &frame[columns.clone()]), &rotation(joint, &target[columns.clone()]), weight);
+                let euler = math::matrix3_to_euler(&math::quaternion_to_matrix3(&blended), rotation_axes);
+                let rotation_columns = columns.zip(joint.channels.iter()).filter(|&(_, channel)| channel.is_rotation());
+                for ((column, _), value) in rotation_columns.zip(euler.iter()) {
+                    eased[column] = *value;
+                }
+            }
+            *frame = if step + 1 == frames { target.to_vec() } else { eased };
+        }
+        Ok(())
+    }
+
+    /// Average of every channel over all frames. Rotations of joints with three distinct rotation channels are averaged as quaternions
+    /// (normalized sum, with signs aligned to the first frame) so angles wrapping around ±180 average correctly; every other channel is
+    /// averaged linearly. Returns nothing if there are no frames.
+    pub fn mean_pose(&self) -> Vec<f64> {
+        let frames = &self.motion.frames;
+        if frames.is_empty() {
+            return Vec::new();
+        }
+        let channels_per_frame = self.hierarchy.root.total_channels() as usize;
+        let mut mean: Vec<f64> = (0..channels_per_frame).map(|column| frames.iter().map(|frame| frame[column]).sum::<f64>() / frames.len() as f64).collect();
+        for (joint, channel_offset) in self.hierarchy.joints().iter().zip(self.hierarchy.channel_offsets()) {
+            let rotation_axes = match joint.rotation_axes() {
+                Some(rotation_axes) => rotation_axes,
+                None => continue,
+            };
+            let columns = channel_offset..channel_offset + joint.channels.len();
+            let rotations: Vec<[f64; 4]> = frames.iter().map(|frame| math::matrix3_to_quaternion(&math::matrix4_rotation(&joint.local_matrix(&frame[columns.clone()])))).collect();
+            let mut sum = [0.0; 4];
+            for rotation in rotations.iter() {
+                let sign = if (0..4).map(|i| rotation[i] * rotations[0][i]).sum::<f64>() < 0.0 { -1.0 } else { 1.0 };
+                for i in 0..4 {
+                    sum[i] += sign * rotation[i];
+                }
+            }
+            let norm = (0..4).map(|i| sum[i] * sum[i]).sum::<f64>().sqrt();
+            let average = [sum[0] / norm, sum[1] / norm, sum[2] / norm, sum[3] / norm];
+            let euler = math::matrix3_to_euler(&math::quaternion_to_matrix3(&average), rotation_axes);
+            let rotation_columns = columns.zip(joint.channels.iter()).filter(|&(_, channel)| channel.is_rotation());
+            for ((column, _), value) in rotation_columns.zip(euler.iter()) {
+                mean[column] = *value;
+            }
+        }
+        mean
+    }
+
+    /// Average angle in degrees that each joint's local orientation turns between consecutive frames, in `Hierarchy::joints` order.
+    /// Joints that barely rotate score close to 0; a clip with fewer than two frames gives 0 for every joint.
+    pub fn orientation_stability(&self) -> Vec<(String, f64)> {
+        let channel_offsets = self.hierarchy.channel_offsets();
+        self.hierarchy.joints().iter().zip(channel_offsets.iter()).map(|(joint, &channel_offset)| {
+            let rotations: Vec<[f64; 4]> = self.motion.frames.iter().map(|frame| {
+                math::matrix3_to_quaternion(&math::matrix4_rotation(&joint.local_matrix(&frame[channel_offset..channel_offset + joint.channels.len()])))
+            }).collect();
+            let total: f64 = rotations.windows(2).map(|pair| {
+                let cos_half_angle = (0..4).map(|i| pair[0][i] * pair[1][i]).sum::<f64>().abs().min(1.0);
+                2.0 * cos_half_angle.acos().to_degrees()
+            }).sum();
+            (joint.name.clone(), total / rotations.len().saturating_sub(1).max(1) as f64)
+        }).collect()
+    }
+
+    /// Serializes the clip, parses it back and checks that the result matches exactly, reporting the first difference found.
+    /// Values are compared exactly on purpose: `f64`'s `Display` writes the shortest string that parses back to the same value, so
+    /// any difference is a real loss. Values that can't be written as BVH numbers (NaN or infinity) fail to parse back.
+    pub fn check_roundtrip(&self) -> Result<(), BvhError> {
+        let mut serialized = Vec::new();
+        serialize(self, &mut serialized).map_err(|e| BvhError::RoundTripMismatch(format!("Couldn't serialize: {}", e)))?;
+        let parsed = parse(&String::from_utf8_lossy(&serialized))?;
+
+        let (joints, parsed_joints) = (self.hierarchy.joints(), parsed.hierarchy.joints());
+        if joints.len() != parsed_joints.len() {
+            return Err(BvhError::RoundTripMismatch(format!("{} joints became {}", joints.len(), parsed_joints.len())));
+        }
+        for (joint, parsed_joint) in joints.iter().zip(parsed_joints.iter()) {
+            if joint.name != parsed_joint.name || joint.channels != parsed_joint.channels {
+                return Err(BvhError::RoundTripMismatch(format!("joint {} became {}", joint.name, parsed_joint.name)));
+            }
+            if joint.offset.to_array() != parsed_joint.offset.to_array() {
+                return Err(BvhError::RoundTripMismatch(format!("OFFSET of {} changed", joint.name)));
+            }
+            if let (JointChildren::EndSite(ref end_site), JointChildren::EndSite(ref parsed_end_site)) = (&joint.children, &parsed_joint.children) {
+                if end_site.offset.to_array() != parsed_end_site.offset.to_array() {
+                    return Err(BvhError::RoundTripMismatch(format!("End Site OFFSET of {} changed", joint.name)));
+                }
+            }
+        }
+        if self.motion.num_frames != parsed.motion.num_frames || self.motion.frames.len() != parsed.motion.frames.len() {
+            return Err(BvhError::RoundTripMismatch(format!("{} frames became {}", self.motion.frames.len(), parsed.motion.frames.len())));
+        }
+        if self.motion.frame_time != parsed.motion.frame_time {
+            return Err(BvhError::RoundTripMismatch(format!("Frame Time {} became {}", self.motion.frame_time, parsed.motion.frame_time)));
+        }
+        for (index, (frame, parsed_frame)) in self.motion.frames.iter().zip(parsed.motion.frames.iter()).enumerate() {
+            if let Some((column, (value, parsed_value))) = frame.iter().zip(parsed_frame.iter()).enumerate().find(|&(_, (a, b))| a != b) {
+                return Err(BvhError::RoundTripMismatch(format!("frame {} column {}: {} became {}", index, column, value, parsed_value)));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "ndarray")]
+impl Bvh {
+    /// Frame data as a `[num_frames, channels_per_frame]` array.
+    pub fn to_ndarray(&self) -> ndarray::Array2<f64> {
+        let channels_per_frame = self.hierarchy.root.total_channels() as usize;
+        ndarray::Array2::from_shape_fn((self.motion.frames.len(), channels_per_frame), |(frame, channel)| self.motion.frames[frame][channel])
+    }
+
+    /// Builds a `Bvh` from a `[num_frames, channels_per_frame]` array, the inverse of `to_ndarray`.
+    pub fn from_ndarray(hierarchy: Hierarchy, frame_time: f64, array: &ndarray::Array2<f64>) -> Result<Bvh, BvhError> {
+        let channels_per_frame = hierarchy.root.total_channels() as usize;
+        if array.ncols() != channels_per_frame {
+            return Err(BvhError::ChannelCountMismatch {
+                expected: channels_per_frame,
+                found: array.ncols(),
+            });
+        }
+        let frames: Vec<Vec<f64>> = array.outer_iter().map(|row| row.to_vec()).collect();
+        Ok(Bvh {
+            hierarchy,
+            motion: Motion {
+                num_frames: frames.len() as u32,
+                frame_time,
+                frames,
+            },
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Hierarchy {
     pub root: Joint,
 }
 
-#[derive(Debug)]
+impl Hierarchy {
+    /// All joints in depth-first order, which is the order their channels appear in each frame.
+    pub fn joints(&self) -> Vec<&Joint> {
+        let mut joints = Vec::new();
+        self.root.collect_joints(&mut joints);
+        joints
+    }
+
+    /// Whether the root has any position channels, without which the skeleton can't move through the scene.
+    pub fn root_has_translation(&self) -> bool {
+        self.root.channels.iter().any(|channel| channel.is_position())
+    }
+
+    /// Human-readable warnings about things that are valid BVH but limit what can be done with the hierarchy.
+    pub fn validate(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if !self.root_has_translation() {
+            warnings.push(format!("Root joint {} has no position channels, so the skeleton can't translate", self.root.name));
+        }
+        warnings
+    }
+
+    /// Sum of the lengths of every bone, i.e. the offsets of all joints below the root and of all end sites.
+    pub fn total_bone_length(&self) -> f64 {
+        let joints = self.joints();
+        let joint_lengths: f64 = joints.iter().skip(1).map(|joint| math::length(&joint.offset.to_array())).sum();
+        let end_site_lengths: f64 = joints.iter().filter_map(|joint| match joint.children {
+            JointChildren::EndSite(ref end_site) => Some(math::length(&end_site.offset.to_array())),
+            JointChildren::Joints(_) => None,
+        }).sum();
+        joint_lengths + end_site_lengths
+    }
+
+    /// Sum of the bone lengths on the path from the root down to `joint`, which is as far as the joint can reach from the root. Returns
+    /// `None` if there's no such joint.
+    pub fn reach_to(&self, joint: &str) -> Option<f64> {
+        let joints = self.joints();
+        let parents = self.parent_indices();
+        let mut current = self.joint_index(joint)?;
+        let mut reach = 0.0;
+        while let Some(parent) = parents[current] {
+            reach += math::length(&joints[current].offset.to_array());
+            current = parent;
+        }
+        Some(reach)
+    }
+
+    /// Every joint's offset divided by `total_bone_length`, giving proportions that don't depend on the skeleton's scale.
+    pub fn normalized_offsets(&self) -> Vec<(String, [f64; 3])> {
+        let total_bone_length = self.total_bone_length();
+        let scale = if total_bone_length > 0.0 { 1.0 / total_bone_length } else { 1.0 };
+        self.joints().iter().map(|joint| (joint.name.clone(), math::scale(&joint.offset.to_array(), scale))).collect()
+    }
+
+    /// Graphviz `digraph` of the skeleton, with a node per joint and end site and an edge per parent/child link.
+    pub fn to_dot(&self) -> String {
+        let joints = self.joints();
+        let parents = self.parent_indices();
+        let mut dot = String::from("digraph skeleton {\n");
+        for (index, joint) in joints.iter().enumerate() {
+            let label = joint.name.replace('\\', "\\\\").replace('"', "\\\"");
+            dot.push_str(&format!("    joint{} [label=\"{}\"];\n", index, label));
+            if let Some(parent) = parents[index] {
+                dot.push_str(&format!("    joint{} -> joint{};\n", parent, index));
+            }
+            if let JointChildren::EndSite(_) = joint.children {
+                dot.push_str(&format!("    end{} [label=\"{} End Site\"];\n", index, label));
+                dot.push_str(&format!("    joint{} -> end{};\n", index, index));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// The channel of every column in a frame.
+    pub fn channel_layout(&self) -> Vec<Channel> {
+        self.joints().iter().flat_map(|joint| joint.channels.iter().cloned()).collect()
+    }
+
+    /// Splits a frame into the position and rotation values of every joint, in `joints` order. The frame must have a value for every
+    /// channel.
+    pub fn decode_frame(&self, frame: &[f64]) -> Result<Vec<(String, JointPose)>, BvhError> {
+        let channels_per_frame = self.root.total_channels() as usize;
+        if frame.len() != channels_per_frame {
+            return Err(BvhError::ChannelCountMismatch {
+                expected: channels_per_frame,
+                found: frame.len(),
+            });
+        }
+        Ok(self.joints().iter().zip(self.channel_offsets()).map(|(joint, channel_offset)| {
+            let mut pose = JointPose {
+                position: None,
+                rotation: None,
+            };
+            for (&channel, &value) in joint.channels.iter().zip(frame[channel_offset..].iter()) {
+                let part = if channel.is_position() { &mut pose.position } else { &mut pose.rotation };
+                part.get_or_insert([0.0; 3])[channel.axis()] = value;
+            }
+            (joint.name.clone(), pose)
+        }).collect())
+    }
+
+    /// Builds a frame from the poses of every joint, the inverse of `decode_frame`. Each pose must have a position exactly when its joint
+    /// has position channels, and likewise for rotations.
+    pub fn encode_frame(&self, poses: &[(String, JointPose)]) -> Result<Vec<f64>, BvhError> {
+        if let Some((name, _)) = poses.iter().find(|(name, _)| self.joint_index(name).is_none()) {
+            return Err(BvhError::UnknownJoint(name.clone()));
+        }
+        let mut frame = Vec::with_capacity(self.root.total_channels() as usize);
+        for joint in self.joints() {
+            let pose = match poses.iter().find(|(name, _)| *name == joint.name) {
+                Some((_, pose)) => pose,
+                None => return Err(BvhError::MissingPose(joint.name.clone())),
+            };
+            if pose.position.is_some() != joint.channels.iter().any(|channel| channel.is_position()) || pose.rotation.is_some() != joint.channels.iter().any(|channel| channel.is_rotation()) {
+                return Err(BvhError::PoseChannelMismatch { joint: joint.name.clone() });
+            }
+            for &channel in joint.channels.iter() {
+                let part = if channel.is_position() { pose.position } else { pose.rotation };
+                frame.push(part.map_or(0.0, |values| values[channel.axis()]));
+            }
+        }
+        Ok(frame)
+    }
+
+    /// Builds a frame in which the joints named in `rotations` take the given rotation values, listed in the order of each joint's
+    /// rotation channels, and every other channel (including the root's position) is 0. Unknown joints are ignored.
+    pub fn frame_from_rotations(&self, rotations: &HashMap<String, [f64; 3]>) -> Vec<f64> {
+        let mut frame = Vec::with_capacity(self.root.total_channels() as usize);
+        for joint in self.joints() {
+            let values = rotations.get(&joint.name);
+            let mut rotation_index = 0;
+            for channel in joint.channels.iter() {
+                if channel.is_position() {
+                    frame.push(0.0);
+                } else {
+                    frame.push(values.and_then(|values| values.get(rotation_index)).cloned().unwrap_or(0.0));
+                    rotation_index += 1;
+                }
+            }
+        }
+        frame
+    }
+
+    /// World transforms of every joint (in `joints` order) for a single frame.
+    pub fn world_transforms(&self, frame: &[f64]) -> Vec<[[f64; 4]; 4]> {
+        let mut transforms = Vec::new();
+        let mut channel_offset = 0;
+        self.root.collect_world_transforms(&math::matrix4_identity(), frame, &mut channel_offset, &mut transforms);
+        transforms
+    }
+
+    /// World positions of every joint (in `joints` order) for a single frame.
+    pub fn world_positions(&self, frame: &[f64]) -> Vec<[f64; 3]> {
+        self.world_transforms(frame).iter().map(math::matrix4_translation).collect()
+    }
+
+    /// World positions of every joint (in `joints` order) with all channels zeroed.
+    pub fn rest_positions(&self) -> Vec<[f64; 3]> {
+        self.world_positions(&vec![0.0; self.root.total_channels() as usize])
+    }
+
+    /// Every bone in the rest pose as a (parent, child) pair of positions, including the bones leading to end sites.
+    pub fn rest_segments(&self) -> Vec<([f64; 3], [f64; 3])> {
+        let positions = self.rest_positions();
+        let mut segments: Vec<([f64; 3], [f64; 3])> = self.parent_indices().iter().enumerate().filter_map(|(index, parent)| parent.map(|parent| (positions[parent], positions[index]))).collect();
+        for (joint, position) in self.joints().iter().zip(positions.iter()) {
+            if let JointChildren::EndSite(ref end_site) = joint.children {
+                segments.push((*position, math::add(position, &end_site.offset.to_array())));
+            }
+        }
+        segments
+    }
+
+    /// Center and radius of a sphere enclosing every joint in the rest pose. The center is the middle of the joints' bounding box.
+    pub fn rest_bounding_sphere(&self) -> ([f64; 3], f64) {
+        let positions = self.rest_positions();
+        let mut min = [f64::INFINITY; 3];
+        let mut max = [f64::NEG_INFINITY; 3];
+        for position in positions.iter() {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(position[axis]);
+                max[axis] = max[axis].max(position[axis]);
+            }
+        }
+        let center = math::scale(&[min[0] + max[0], min[1] + max[1], min[2] + max[2]], 0.5);
+        let radius = positions.iter().map(|position| math::length(&math::sub(position, &center))).fold(0.0, f64::max);
+        (center, radius)
+    }
+
+    /// Recovers channel values reproducing `positions`, which holds the world position of every joint (in `joints` order) for each frame.
+    /// Each joint's rotation is solved from the directions to its children: joints with two non-collinear children are fully determined,
+    /// joints with a single child get the smallest rotation away from their parent's orientation (twist about the bone can't be recovered
+    /// from positions alone), and joints with only an end site keep their parent's orientation. Joints must have either no rotation
+    /// channels or exactly three distinct ones. Positions carry no timing, so the returned motion's `frame_time` is 0 for the caller to set.
+    pub fn solve_local_rotations(&self, positions: &[Vec<[f64; 3]>]) -> Result<Motion, BvhError> {
+        let joints = self.joints();
+        let parents = self.parent_indices();
+        let rotation_axes = joints.iter().map(|joint| match joint.rotation_axes() {
+            Some(axes) => Ok(Some(axes)),
+            None if !joint.channels.iter().any(|channel| channel.is_rotation()) => Ok(None),
+            None => Err(BvhError::UnsupportedChannels {
+                joint: joint.name.clone(),
+            }),
+        }).collect::<Result<Vec<Option<[usize; 3]>>, BvhError>>()?;
+
+        let mut frames = Vec::with_capacity(positions.len());
+        for frame_positions in positions.iter() {
+            if frame_positions.len() != joints.len() {
+                return Err(BvhError::JointCountMismatch {
+                    expected: joints.len(),
+                    found: frame_positions.len(),
+                });
+            }
+
+            let mut world_rotations: Vec<math::Matrix3> = Vec::with_capacity(joints.len());
+            let mut frame = Vec::with_capacity(self.root.total_channels() as usize);
+            for (index, joint) in joints.iter().enumerate() {
+                let (parent_rotation, parent_position) = match parents[index] {
+                    Some(parent) => (world_rotations[parent], frame_positions[parent]),
+                    None => (math::matrix3_identity(), [0.0; 3]),
+                };
+                let position = frame_positions[index];
+
+                let targets: Vec<([f64; 3], [f64; 3])> = (index + 1..joints.len()).filter(|&child| parents[child] == Some(index)).map(|child| {
+                    (joints[child].offset.to_array(), math::sub(&frame_positions[child], &position))
+                }).filter(|&(rest, target)| math::length(&rest) > 1e-9 && math::length(&target) > 1e-9).collect();
+                let world_rotation = match (rotation_axes[index], targets.first()) {
+                    (Some(_), Some(&(first_rest, first_target))) => {
+                        let second = targets.iter().skip(1).find(|&&(rest, _)| math::length(&math::cross(&math::normalize(&first_rest), &math::normalize(&rest))) > 1e-6);
+                        match second {
+                            Some(&(second_rest, second_target)) => math::rotation_from_pairs((&first_rest, &second_rest), (&first_target, &second_target)),
+                            None => {
+                                let predicted = math::matrix3_mul_vector(&parent_rotation, &first_rest);
+                                math::matrix3_mul(&math::rotation_between(&predicted, &first_target), &parent_rotation)
+                            }
+                        }
+                    }
+                    _ => parent_rotation,
+                };
+
+                let parent_inverse = math::matrix3_transpose(&parent_rotation);
+                let euler = rotation_axes[index].map(|axes| math::matrix3_to_euler(&math::matrix3_mul(&parent_inverse, &world_rotation), axes));
+                let translation = math::sub(&math::matrix3_mul_vector(&parent_inverse, &math::sub(&position, &parent_position)), &joint.offset.to_array());
+                let mut rotation_index = 0;
+                for channel in joint.channels.iter() {
+                    if channel.is_position() {
+                        frame.push(translation[channel.axis()]);
+                    } else {
+                        frame.push(euler.map_or(0.0, |euler| euler[rotation_index]));
+                        rotation_index += 1;
+                    }
+                }
+
+                world_rotations.push(world_rotation);
+            }
+            frames.push(frame);
+        }
+
+        Ok(Motion {
+            num_frames: frames.len() as u32,
+            frame_time: 0.0,
+            frames,
+        })
+    }
+
+    /// Swaps the subtrees rooted at joints `a` and `b`, which must not be ancestors of each other.
+    /// This only changes the hierarchy; use `Bvh::swap_subtrees` to keep frame data consistent.
+    pub fn swap_subtrees(&mut self, a: &str, b: &str) -> Result<(), BvhError> {
+        let a_path = self.root.path_to(a).ok_or_else(|| BvhError::UnknownJoint(a.into()))?;
+        let b_path = self.root.path_to(b).ok_or_else(|| BvhError::UnknownJoint(b.into()))?;
+        if a_path == b_path {
+            return Ok(());
+        }
+        if b_path.starts_with(&a_path) || a_path.starts_with(&b_path) {
+            let (ancestor, descendant) = if a_path.len() < b_path.len() { (a, b) } else { (b, a) };
+            return Err(BvhError::NestedJoints {
+                ancestor: ancestor.into(),
+                descendant: descendant.into(),
+            });
+        }
+
+        let common = a_path.iter().zip(b_path.iter()).take_while(|&(a_index, b_index)| a_index == b_index).count();
+        let (first_path, second_path) = if a_path[common] < b_path[common] { (&a_path, &b_path) } else { (&b_path, &a_path) };
+        if let JointChildren::Joints(ref mut children) = self.root.descendant_mut(&a_path[..common]).children {
+            let (left, right) = children.split_at_mut(second_path[common]);
+            let first = left[first_path[common]].descendant_mut(&first_path[common + 1..]);
+            let second = right[0].descendant_mut(&second_path[common + 1..]);
+            mem::swap(first, second);
+        }
+        Ok(())
+    }
+
+    /// Fixes problems that stop a hand-edited hierarchy from being serialized and parsed back: joints with no children get an end
+    /// site at their origin, and repeated joint names get a numeric suffix. Returns a description of each repair.
+    pub fn repair(&mut self) -> Vec<String> {
+        let mut taken: HashSet<String> = self.joints().iter().map(|joint| joint.name.clone()).collect();
+        let mut seen = HashSet::new();
+        let mut repairs = Vec::new();
+        self.root.repair(&mut taken, &mut seen, &mut repairs);
+        repairs
+    }
+
+    /// Copy of `a` with every joint and end site offset linearly interpolated toward `b` by `t` (0 gives `a`, 1 gives `b`). Both
+    /// hierarchies must have the same joint names, channels and structure.
+    pub fn blend_offsets(a: &Hierarchy, b: &Hierarchy, t: f64) -> Result<Hierarchy, BvhError> {
+        Ok(Hierarchy {
+            root: a.root.blend_offsets(&b.root, t)?,
+        })
+    }
+
+    /// Axis the skeleton is mirrored across, guessed from the rest positions of left/right joint pairs: the axis along which paired
+    /// joints are furthest apart in total. Pairs are found by name, swapping `Left`/`Right` (in any case) or a leading or trailing
+    /// `L`/`R` separated by `_` or `.`, as in `LeftArm`, `l_elbow` or `hand.R`. Falls back to X if no pairs are found.
+    pub fn symmetry_plane(&self) -> Axis {
+        let joints = self.joints();
+        let positions = self.rest_positions();
+        let mut separation = [0.0; 3];
+        for (index, joint) in joints.iter().enumerate() {
+            let other = match mirrored_name(&joint.name).and_then(|name| self.joint_index(&name)) {
+                Some(other) if other != index => other,
+                _ => continue,
+            };
+            let difference = math::sub(&positions[index], &positions[other]);
+            for axis in 0..3 {
+                separation[axis] += difference[axis].abs();
+            }
+        }
+        if separation[1] > separation[0] && separation[1] >= separation[2] {
+            Axis::Y
+        } else if separation[2] > separation[0] && separation[2] > separation[1] {
+            Axis::Z
+        } else {
+            Axis::X
+        }
+    }
+
+    fn subtree_columns(&self, name: &str) -> Option<(usize, usize)> {
+        let joint_index = self.joint_index(name)?;
+        let start = self.channel_offsets()[joint_index];
+        Some((start, start + self.joints()[joint_index].total_channels() as usize))
+    }
+
+    fn parent_indices(&self) -> Vec<Option<usize>> {
+        let mut parents = Vec::new();
+        self.root.collect_parents(None, &mut parents);
+        parents
+    }
+
+    fn joint_index(&self, name: &str) -> Option<usize> {
+        self.joints().iter().position(|joint| joint.name == name)
+    }
+
+    fn channel_offsets(&self) -> Vec<usize> {
+        let mut channel_offset = 0;
+        self.joints().iter().map(|joint| {
+            let offset = channel_offset;
+            channel_offset += joint.channels.len();
+            offset
+        }).collect()
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Joint {
     pub name: String,
     pub offset: Offset,
@@ -40,16 +1408,157 @@ impl Joint {
             JointChildren::EndSite(_) => 0,
         }
     }
+
+    /// Transform from this joint's space to its parent's, given this joint's own channel values (in `channels` order).
+    /// The offset and any position channels translate the joint, then the rotation channels are applied in order.
+    pub fn local_matrix(&self, channel_values: &[f64]) -> [[f64; 4]; 4] {
+        let mut translation = [self.offset.x, self.offset.y, self.offset.z];
+        let mut rotation = math::matrix3_identity();
+        for (channel, &value) in self.channels.iter().zip(channel_values) {
+            if channel.is_position() {
+                translation[channel.axis()] += value;
+            } else {
+                rotation = math::matrix3_mul(&rotation, &math::matrix3_rotation(channel.axis(), value));
+            }
+        }
+        math::matrix4_from_parts(&rotation, &translation)
+    }
+
+    // Axes of this joint's rotation channels, if it has exactly three distinct ones.
+    fn rotation_axes(&self) -> Option<[usize; 3]> {
+        let axes: Vec<usize> = self.channels.iter().filter(|channel| channel.is_rotation()).map(|channel| channel.axis()).collect();
+        match axes.len() {
+            3 if axes[0] != axes[1] && axes[1] != axes[2] && axes[0] != axes[2] => Some([axes[0], axes[1], axes[2]]),
+            _ => None,
+        }
+    }
+
+    fn collect_world_transforms(&self, parent_transform: &[[f64; 4]; 4], frame: &[f64], channel_offset: &mut usize, transforms: &mut Vec<[[f64; 4]; 4]>) {
+        let channel_values = &frame[*channel_offset..*channel_offset + self.channels.len()];
+        *channel_offset += self.channels.len();
+        let transform = math::matrix4_mul(parent_transform, &self.local_matrix(channel_values));
+        transforms.push(transform);
+        if let JointChildren::Joints(ref children) = self.children {
+            for child in children.iter() {
+                child.collect_world_transforms(&transform, frame, channel_offset, transforms);
+            }
+        }
+    }
+
+    fn path_to(&self, name: &str) -> Option<Vec<usize>> {
+        if self.name == name {
+            return Some(Vec::new());
+        }
+        if let JointChildren::Joints(ref children) = self.children {
+            for (index, child) in children.iter().enumerate() {
+                if let Some(mut path) = child.path_to(name) {
+                    path.insert(0, index);
+                    return Some(path);
+                }
+            }
+        }
+        None
+    }
+
+    fn descendant_mut(&mut self, path: &[usize]) -> &mut Joint {
+        match path.split_first() {
+            Some((&index, rest)) => match self.children {
+                JointChildren::Joints(ref mut children) => children[index].descendant_mut(rest),
+                JointChildren::EndSite(_) => unreachable!(),
+            },
+            None => self,
+        }
+    }
+
+    fn repair(&mut self, taken: &mut HashSet<String>, seen: &mut HashSet<String>, repairs: &mut Vec<String>) {
+        if !seen.insert(self.name.clone()) {
+            let renamed = (2..).map(|suffix| format!("{}{}", self.name, suffix)).find(|name| !taken.contains(name)).unwrap();
+            repairs.push(format!("Renamed duplicate joint {} to {}", self.name, renamed));
+            taken.insert(renamed.clone());
+            seen.insert(renamed.clone());
+            self.name = renamed;
+        }
+        let empty = matches!(self.children, JointChildren::Joints(ref children) if children.is_empty());
+        if empty {
+            repairs.push(format!("Added an end site to {}, which had no children", self.name));
+            self.children = JointChildren::EndSite(EndSite {
+                offset: Offset {
+                    x: 0.0,
+                    y: 0.0,
+                    z: 0.0,
+                },
+            });
+        }
+        if let JointChildren::Joints(ref mut children) = self.children {
+            for child in children.iter_mut() {
+                child.repair(taken, seen, repairs);
+            }
+        }
+    }
+
+    fn blend_offsets(&self, other: &Joint, t: f64) -> Result<Joint, BvhError> {
+        if self.name != other.name || self.channels != other.channels {
+            return Err(BvhError::TopologyMismatch { joint: self.name.clone() });
+        }
+        let children = match (&self.children, &other.children) {
+            (JointChildren::Joints(a), JointChildren::Joints(b)) if a.len() == b.len() => {
+                JointChildren::Joints(a.iter().zip(b.iter()).map(|(a, b)| a.blend_offsets(b, t)).collect::<Result<Vec<Joint>, BvhError>>()?)
+            }
+            (JointChildren::EndSite(a), JointChildren::EndSite(b)) => JointChildren::EndSite(EndSite {
+                offset: a.offset.lerp(&b.offset, t),
+            }),
+            _ => return Err(BvhError::TopologyMismatch { joint: self.name.clone() }),
+        };
+        Ok(Joint {
+            name: self.name.clone(),
+            offset: self.offset.lerp(&other.offset, t),
+            channels: self.channels.clone(),
+            children,
+        })
+    }
+
+    fn collect_parents(&self, parent: Option<usize>, parents: &mut Vec<Option<usize>>) {
+        let index = parents.len();
+        parents.push(parent);
+        if let JointChildren::Joints(ref children) = self.children {
+            for child in children.iter() {
+                child.collect_parents(Some(index), parents);
+            }
+        }
+    }
+
+    fn collect_joints<'a>(&'a self, joints: &mut Vec<&'a Joint>) {
+        joints.push(self);
+        if let JointChildren::Joints(ref children) = self.children {
+            for child in children.iter() {
+                child.collect_joints(joints);
+            }
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Offset {
     pub x: f64,
     pub y: f64,
     pub z: f64,
 }
 
-#[derive(Debug)]
+impl Offset {
+    fn to_array(&self) -> [f64; 3] {
+        [self.x, self.y, self.z]
+    }
+
+    fn lerp(&self, other: &Offset, t: f64) -> Offset {
+        Offset {
+            x: self.x + (other.x - self.x) * t,
+            y: self.y + (other.y - self.y) * t,
+            z: self.z + (other.z - self.z) * t,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Channel {
     XPosition,
     YPosition,
@@ -59,41 +1568,407 @@ pub enum Channel {
     ZRotation,
 }
 
-#[derive(Debug)]
+impl Channel {
+    pub fn is_position(&self) -> bool {
+        matches!(*self, Channel::XPosition | Channel::YPosition | Channel::ZPosition)
+    }
+
+    pub fn is_rotation(&self) -> bool {
+        !self.is_position()
+    }
+
+    fn rotation(axis: usize) -> Channel {
+        match axis {
+            0 => Channel::XRotation,
+            1 => Channel::YRotation,
+            _ => Channel::ZRotation,
+        }
+    }
+
+    fn axis(&self) -> usize {
+        match *self {
+            Channel::XPosition | Channel::XRotation => 0,
+            Channel::YPosition | Channel::YRotation => 1,
+            Channel::ZPosition | Channel::ZRotation => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl Axis {
+    fn index(&self) -> usize {
+        match *self {
+            Axis::X => 0,
+            Axis::Y => 1,
+            Axis::Z => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum JointChildren {
     Joints(Vec<Joint>),
     EndSite(EndSite),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct EndSite {
     pub offset: Offset,
 }
 
-#[derive(Debug)]
+/// One joint's channel values from a frame, indexed by axis (x, y, z). A part is `None` if the joint has no channels of that kind
+/// and components whose channel is missing are 0.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JointPose {
+    pub position: Option<[f64; 3]>,
+    pub rotation: Option<[f64; 3]>,
+}
+
+#[derive(Debug, Clone)]
 pub struct Motion {
     pub num_frames: u32,
     pub frame_time: f64,
     pub frames: Vec<Vec<f64>>,
 }
 
-pub fn parse(input: &str) -> Result<Bvh, String> {
-    let mut pairs = BvhParser::parse(Rule::bvh, &input).map_err(|e| format!("Couldn't parse BVH: {:?}", e))?;
+impl Motion {
+    /// Length of the clip in seconds, counting each frame as lasting `frame_time`.
+    pub fn duration(&self) -> f64 {
+        self.frames.len() as f64 * self.frame_time
+    }
+
+    /// Channel values at `time` seconds, linearly interpolated between the surrounding frames and clamped to the first/last frame.
+    pub fn sample(&self, time: f64) -> Vec<f64> {
+        let last = match self.frames.len() {
+            0 => return Vec::new(),
+            len => len - 1,
+        };
+        let position = if self.frame_time > 0.0 { (time / self.frame_time).clamp(0.0, last as f64) } else { 0.0 };
+        let index = (position.floor() as usize).min(last);
+        let next = (index + 1).min(last);
+        let t = position - index as f64;
+        self.frames[index].iter().zip(self.frames[next].iter()).map(|(a, b)| a + (b - a) * t).collect()
+    }
+
+    /// Index of the frame nearest to `time`, clamped to the clip. Returns 0 if there are no frames or `frame_time` isn't positive.
+    pub fn frame_index_at_time(&self, time: f64) -> usize {
+        if self.frames.is_empty() || self.frame_time <= 0.0 {
+            return 0;
+        }
+        (time / self.frame_time).round().clamp(0.0, (self.frames.len() - 1) as f64) as usize
+    }
+
+    /// Resamples the motion at `new_frame_time`, covering the same span from the first frame to the last.
+    /// `channels` is the layout of each frame (see `Hierarchy::channel_layout`): position channels are interpolated with
+    /// `position_interpolation`, while rotation channels are always interpolated linearly.
+    pub fn resample(&self, new_frame_time: f64, channels: &[Channel], position_interpolation: Interpolation) -> Motion {
+        if new_frame_time <= 0.0 || self.frame_time <= 0.0 || self.frames.is_empty() {
+            return self.clone();
+        }
+        let last = self.frames.len() - 1;
+        let span = last as f64 * self.frame_time;
+        let num_frames = (span / new_frame_time + 1e-9).floor() as usize + 1;
+        let frames = (0..num_frames).map(|frame| {
+            let position = (frame as f64 * new_frame_time / self.frame_time).min(last as f64);
+            let index = (position.floor() as usize).min(last);
+            let t = position - index as f64;
+            let frame_at = |offset: isize| &self.frames[(index as isize + offset).max(0).min(last as isize) as usize];
+            let (previous, current, next, after_next) = (frame_at(-1), frame_at(0), frame_at(1), frame_at(2));
+            (0..current.len()).map(|column| {
+                let is_position = channels.get(column).is_some_and(|channel| channel.is_position());
+                match position_interpolation {
+                    Interpolation::CatmullRom if is_position => {
+                        let (p0, p1, p2, p3) = (previous[column], current[column], next[column], after_next[column]);
+                        0.5 * (2.0 * p1 + (p2 - p0) * t + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t * t + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t * t * t)
+                    }
+                    _ => current[column] + (next[column] - current[column]) * t,
+                }
+            }).collect()
+        }).collect::<Vec<Vec<f64>>>();
+        Motion {
+            num_frames: frames.len() as u32,
+            frame_time: new_frame_time,
+            frames,
+        }
+    }
+
+    /// Sub-motions of `window_frames` frames, starting every `stride` frames. Trailing frames that don't fill a whole window are dropped.
+    pub fn windows(&self, window_frames: usize, stride: usize) -> Vec<Motion> {
+        if window_frames == 0 || stride == 0 || window_frames > self.frames.len() {
+            return Vec::new();
+        }
+        (0..self.frames.len() - window_frames + 1).step_by(stride).map(|start| Motion {
+            num_frames: window_frames as u32,
+            frame_time: self.frame_time,
+            frames: self.frames[start..start + window_frames].to_vec(),
+        }).collect()
+    }
+
+    /// Copy of the motion starting `frames` frames later, wrapping around the end as for a looping clip. Negative shifts go backward.
+    pub fn phase_shift(&self, frames: isize) -> Motion {
+        let mut shifted = self.frames.clone();
+        if !shifted.is_empty() {
+            let shift = frames.rem_euclid(shifted.len() as isize) as usize;
+            shifted.rotate_left(shift);
+        }
+        Motion {
+            num_frames: self.num_frames,
+            frame_time: self.frame_time,
+            frames: shifted,
+        }
+    }
+
+    /// Rearranges the columns of every frame so that new column `i` holds old column `permutation[i]`. `permutation` must contain
+    /// each column index exactly once, and every frame must have that many columns.
+    pub fn reorder_columns(&mut self, permutation: &[usize]) -> Result<(), BvhError> {
+        let mut used = vec![false; permutation.len()];
+        for &column in permutation.iter() {
+            if column >= used.len() || mem::replace(&mut used[column], true) {
+                return Err(BvhError::InvalidPermutation { columns: permutation.len() });
+            }
+        }
+        if let Some(frame) = self.frames.iter().find(|frame| frame.len() != permutation.len()) {
+            return Err(BvhError::ChannelCountMismatch {
+                expected: permutation.len(),
+                found: frame.len(),
+            });
+        }
+        for frame in self.frames.iter_mut() {
+            *frame = permutation.iter().map(|&column| frame[column]).collect();
+        }
+        Ok(())
+    }
+
+    /// Collapses each run of consecutive frames whose channels are all within `epsilon` of the run's first frame into that first frame.
+    /// Returns the original indices of the frames that were kept. This changes the clip's timing, since dropped frames aren't replaced.
+    pub fn dedupe_consecutive(&mut self, epsilon: f64) -> Vec<usize> {
+        let mut retained: Vec<usize> = Vec::new();
+        for (index, frame) in self.frames.iter().enumerate() {
+            let duplicate = retained.last().is_some_and(|&kept| self.frames[kept].iter().zip(frame.iter()).all(|(a, b)| (a - b).abs() <= epsilon));
+            if !duplicate {
+                retained.push(index);
+            }
+        }
+        self.frames = retained.iter().map(|&index| self.frames[index].clone()).collect();
+        self.num_frames = self.frames.len() as u32;
+        retained
+    }
+
+    /// Replaces every NaN or infinite channel value with `replacement`, returning how many were replaced.
+    pub fn sanitize(&mut self, replacement: f64) -> usize {
+        let mut replaced = 0;
+        for value in self.frames.iter_mut().flat_map(|frame| frame.iter_mut()) {
+            if !value.is_finite() {
+                *value = replacement;
+                replaced += 1;
+            }
+        }
+        replaced
+    }
+
+    /// Number of whole frames spanned by `seconds`, rounded to the nearest frame. Returns 0 if `frame_time` isn't positive.
+    pub fn frames_for_duration(&self, seconds: f64) -> usize {
+        if self.frame_time <= 0.0 {
+            return 0;
+        }
+        (seconds / self.frame_time).round().max(0.0) as usize
+    }
+}
+
+// 64-bit FNV-1a, used where hashes must not change between builds
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Fnv1a {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes.iter() {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+enum RerootedChannels {
+    Copied { start: usize, end: usize },
+    Solved { index: usize, parent: Option<usize>, offset: [f64; 3], rotation_axes: [usize; 3] },
+}
+
+// Builds the rerooted copy of `joints[index]`, whose new parent is `joints[new_parent]`, and records how to fill in its channels.
+fn build_rerooted_joint(joints: &[&Joint], parents: &[Option<usize>], channel_offsets: &[usize], index: usize, new_parent: Option<usize>, offset: [f64; 3], plan: &mut Vec<RerootedChannels>) -> Joint {
+    let joint = joints[index];
+    let rotation_axes = joint.rotation_axes().unwrap_or([2, 0, 1]);
+    plan.push(RerootedChannels::Solved {
+        index,
+        parent: new_parent,
+        offset,
+        rotation_axes,
+    });
+
+    let mut children = Vec::new();
+    for child in (index + 1..joints.len()).filter(|&child| parents[child] == Some(index) && Some(child) != new_parent) {
+        let start = channel_offsets[child];
+        plan.push(RerootedChannels::Copied {
+            start,
+            end: start + joints[child].total_channels() as usize,
+        });
+        children.push(joints[child].clone());
+    }
+    if let Some(parent) = parents[index] {
+        let offset = math::sub(&[0.0; 3], &joint.offset.to_array());
+        children.push(build_rerooted_joint(joints, parents, channel_offsets, parent, Some(index), offset, plan));
+    }
+
+    Joint {
+        name: joint.name.clone(),
+        offset: Offset {
+            x: offset[0],
+            y: offset[1],
+            z: offset[2],
+        },
+        channels: vec![Channel::XPosition, Channel::YPosition, Channel::ZPosition].into_iter().chain(rotation_axes.iter().map(|&axis| Channel::rotation(axis))).collect(),
+        children: if !children.is_empty() {
+            JointChildren::Joints(children)
+        } else {
+            JointChildren::EndSite(EndSite {
+                offset: Offset {
+                    x: 0.0,
+                    y: 0.0,
+                    z: 0.0,
+                },
+            })
+        },
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    Linear,
+    CatmullRom,
+}
+
+/// Checks that every frame of every take has as many channels as `hierarchy`, reporting the first take that doesn't.
+pub fn validate_takes(hierarchy: &Hierarchy, takes: &[Motion]) -> Result<(), BvhError> {
+    let expected = hierarchy.root.total_channels() as usize;
+    for (take, motion) in takes.iter().enumerate() {
+        if let Some(frame) = motion.frames.iter().find(|frame| frame.len() != expected) {
+            return Err(BvhError::TakeChannelMismatch {
+                take,
+                expected,
+                found: frame.len(),
+            });
+        }
+    }
+    Ok(())
+}
+
+// First and last of frames `0..count` for which `changed` holds, if there are any.
+fn changed_span<F: Fn(usize) -> bool>(count: usize, changed: F) -> Option<(usize, usize)> {
+    let first = (0..count).position(&changed)?;
+    let last = (0..count).rev().find(|&frame| changed(frame)).unwrap();
+    Some((first, last))
+}
+
+// Name of the joint on the other side of the body from `name`, if `name` marks a side (see `Hierarchy::symmetry_plane`).
+fn mirrored_name(name: &str) -> Option<String> {
+    for &(side, other) in [("Left", "Right"), ("Right", "Left"), ("left", "right"), ("right", "left"), ("LEFT", "RIGHT"), ("RIGHT", "LEFT")].iter() {
+        if name.contains(side) {
+            return Some(name.replacen(side, other, 1));
+        }
+    }
+    for &(side, other) in [("L", "R"), ("R", "L"), ("l", "r"), ("r", "l")].iter() {
+        for separator in ["_", "."].iter() {
+            if let Some(rest) = name.strip_prefix(&format!("{}{}", side, separator)) {
+                return Some(format!("{}{}{}", other, separator, rest));
+            }
+            if let Some(rest) = name.strip_suffix(&format!("{}{}", separator, side)) {
+                return Some(format!("{}{}{}", rest, separator, other));
+            }
+        }
+    }
+    None
+}
+
+/// Resamples every take to `new_frame_time` (see `Motion::resample`), interpolating all channels linearly. Every frame of every
+/// take must have `channels_per_frame` values; otherwise the first take that doesn't is reported, as in `validate_takes`.
+pub fn resample_takes(takes: &[Motion], new_frame_time: f64, channels_per_frame: usize) -> Result<Vec<Motion>, BvhError> {
+    takes.iter().enumerate().map(|(take, motion)| {
+        if let Some(frame) = motion.frames.iter().find(|frame| frame.len() != channels_per_frame) {
+            return Err(BvhError::TakeChannelMismatch {
+                take,
+                expected: channels_per_frame,
+                found: frame.len(),
+            });
+        }
+        Ok(motion.resample(new_frame_time, &[], Interpolation::Linear))
+    }).collect()
+}
+
+pub fn parse(input: &str) -> Result<Bvh, BvhError> {
+    parse_with_options(input, &ParseOptions::default())
+}
+
+pub fn parse_with_options(input: &str, options: &ParseOptions) -> Result<Bvh, BvhError> {
+    let mut pairs = BvhParser::parse(Rule::bvh, input).map_err(|e| BvhError::Parse(format!("{:?}", e)))?;
 
     let mut bvh_pairs = pairs.find(|pair| pair.as_rule() == Rule::bvh).unwrap().into_inner();
 
-    let mut hierarchy_pairs = bvh_pairs.find(|pair| pair.as_rule() == Rule::hierarchy).unwrap().into_inner();
-    let mut root_pairs = hierarchy_pairs.find(|pair| pair.as_rule() == Rule::root_joint).unwrap().into_inner();
-    let root_joint_body_pairs = root_pairs.find(|pair| pair.as_rule() == Rule::joint_body).unwrap().into_inner();
-    let root = parse_joint(root_joint_body_pairs);
+    let hierarchy_pairs = bvh_pairs.find(|pair| pair.as_rule() == Rule::hierarchy).unwrap().into_inner();
+    let root = parse_hierarchy(hierarchy_pairs, options)?.root;
 
     let mut motion_pairs = bvh_pairs.find(|pair| pair.as_rule() == Rule::motion).unwrap().into_inner();
     let mut frames_pairs = motion_pairs.find(|pair| pair.as_rule() == Rule::frames).unwrap().into_inner();
-    let num_frames = frames_pairs.find(|pair| pair.as_rule() == Rule::integer).unwrap().as_str().parse::<u32>().unwrap();
-    let frame_time = parse_f64(&mut frames_pairs);
-    let mut frames = Vec::new();
-    let mut frame = Vec::new();
+    let mut num_frames = frames_pairs.find(|pair| pair.as_rule() == Rule::integer).unwrap().as_str().parse::<u32>().map_err(|e| BvhError::Parse(format!("Invalid frame count: {}", e)))?;
+    let mut frame_time_pairs = frames_pairs.find(|pair| pair.as_rule() == Rule::frame_time).unwrap().into_inner();
+    let mut frame_time = parse_f64(&mut frame_time_pairs);
+    if frame_time_pairs.any(|pair| pair.as_rule() == Rule::fps) {
+        if !options.lenient {
+            return Err(BvhError::Parse("Frame Time given in fps requires lenient parsing".into()));
+        }
+        if frame_time <= 0.0 {
+            return Err(BvhError::Parse(format!("Frame Time of {}fps must be positive", frame_time)));
+        }
+        frame_time = 1.0 / frame_time;
+    }
+    let separator_count = frames_pairs.clone().filter(|pair| pair.as_rule() == Rule::value_separator).count();
+    let comma_separated = separator_count > 0;
+    if !options.lenient && comma_separated {
+        return Err(BvhError::Parse("Comma-separated frame values require lenient parsing".into()));
+    }
     let total_channels = root.total_channels();
+    let value_count = frames_pairs.clone().filter(|pair| pair.as_rule() == Rule::float).count();
+    // A decimal comma (`0,5`) reads as two values, shifting every later column, so comma-separated data has to fill the declared
+    // frames exactly with a comma between every two values of a frame
+    let values_filled = value_count as u64 == num_frames as u64 * total_channels as u64;
+    if comma_separated && (!values_filled || separator_count + (num_frames as usize) < value_count) {
+        return Err(BvhError::Parse(format!("{} frame values with {} commas don't form {} comma-separated frames of {} channels (decimals must use a point)", value_count, separator_count, num_frames, total_channels)));
+    }
+    let available_frames = if total_channels > 0 { (value_count / total_channels as usize) as u32 } else { 0 };
+    if num_frames > available_frames {
+        // Every value takes at least one character, so a count the whole input couldn't hold is garbage rather than a short file
+        if !options.lenient && num_frames as u64 * total_channels as u64 > input.len() as u64 {
+            return Err(BvhError::FrameCountMismatch {
+                declared: num_frames,
+                found: available_frames,
+            });
+        }
+        if options.lenient {
+            num_frames = available_frames;
+        }
+    }
+    let mut frames = Vec::with_capacity(num_frames.min(available_frames) as usize);
+    let mut frame = Vec::new();
     for (index, value) in frames_pairs.filter(|pair| pair.as_rule() == Rule::float).map(|pair| pair.as_str().parse::<f64>().unwrap()).enumerate() {
         frame.push(value);
         if (index as u32) % total_channels == total_channels - 1 {
@@ -102,60 +1977,96 @@ pub fn parse(input: &str) -> Result<Bvh, String> {
         }
     }
     let motion = Motion {
-        num_frames: num_frames,
-        frame_time: frame_time,
-        frames: frames,
+        num_frames,
+        frame_time,
+        frames,
     };
 
     Ok(Bvh {
         hierarchy: Hierarchy {
-            root: root,
+            root,
         },
-        motion: motion,
+        motion,
+    })
+}
+
+/// Parses just the HIERARCHY block, ignoring everything after it (the MOTION block isn't even checked), for when only the skeleton is needed.
+pub fn parse_hierarchy_only(input: &str) -> Result<Hierarchy, BvhError> {
+    parse_hierarchy_only_with_options(input, &ParseOptions::default())
+}
+
+pub fn parse_hierarchy_only_with_options(input: &str, options: &ParseOptions) -> Result<Hierarchy, BvhError> {
+    let mut pairs = BvhParser::parse(Rule::hierarchy_only, input).map_err(|e| BvhError::Parse(format!("{:?}", e)))?;
+
+    let mut hierarchy_only_pairs = pairs.find(|pair| pair.as_rule() == Rule::hierarchy_only).unwrap().into_inner();
+    let hierarchy_pairs = hierarchy_only_pairs.find(|pair| pair.as_rule() == Rule::hierarchy).unwrap().into_inner();
+    parse_hierarchy(hierarchy_pairs, options)
+}
+
+fn parse_hierarchy(mut hierarchy_pairs: Pairs<Rule>, options: &ParseOptions) -> Result<Hierarchy, BvhError> {
+    let mut root_pairs = hierarchy_pairs.find(|pair| pair.as_rule() == Rule::root_joint).unwrap().into_inner();
+    let root_joint_body_pairs = root_pairs.find(|pair| pair.as_rule() == Rule::joint_body).unwrap().into_inner();
+    Ok(Hierarchy {
+        root: parse_joint(root_joint_body_pairs, options)?,
     })
 }
 
-fn parse_joint(mut joint_body_pairs: Pairs<Rule>) -> Joint {
-    let name = joint_body_pairs.find(|pair| pair.as_rule() == Rule::identifier).unwrap().as_str().into();
-    let mut offset_pairs = joint_body_pairs.find(|pair| pair.as_rule() == Rule::offset).unwrap().into_inner();
-    let offset = parse_offset(&mut offset_pairs);
-    let channel_pairs = joint_body_pairs.find(|pair| pair.as_rule() == Rule::channels).unwrap().into_inner();
-    let joints: Vec<Joint> = joint_body_pairs.clone().filter(|pair| pair.as_rule() == Rule::joint).map(|pair| {
+fn parse_joint(mut joint_body_pairs: Pairs<Rule>, options: &ParseOptions) -> Result<Joint, BvhError> {
+    let name: String = joint_body_pairs.find(|pair| pair.as_rule() == Rule::identifier).unwrap().as_str().into();
+    // OFFSET and CHANNELS may come in either order
+    let offset_pairs = joint_body_pairs.clone().find(|pair| pair.as_rule() == Rule::offset).unwrap().into_inner();
+    let offset = parse_offset(offset_pairs, &name, options)?;
+    let channel_pairs = joint_body_pairs.clone().find(|pair| pair.as_rule() == Rule::channels).unwrap().into_inner();
+    let channels = channel_pairs.filter(|pair| pair.as_rule() == Rule::channel).map(|pair| {
+        if let Some(&channel) = options.channel_aliases.get(pair.as_str()) {
+            return Ok(channel);
+        }
+        match pair.as_str() {
+            "Xposition" => Ok(Channel::XPosition),
+            "Yposition" => Ok(Channel::YPosition),
+            "Zposition" => Ok(Channel::ZPosition),
+            "Xrotation" => Ok(Channel::XRotation),
+            "Yrotation" => Ok(Channel::YRotation),
+            "Zrotation" => Ok(Channel::ZRotation),
+            other => Err(BvhError::Parse(format!("Unknown channel {} for joint {}", other, name))),
+        }
+    }).collect::<Result<Vec<Channel>, BvhError>>()?;
+    let joints = joint_body_pairs.clone().filter(|pair| pair.as_rule() == Rule::joint).map(|pair| {
         let body_pairs = pair.into_inner().find(|pair| pair.as_rule() == Rule::joint_body).unwrap().into_inner();
-        parse_joint(body_pairs)
-    }).collect();
-    let children = if joints.len() > 0 {
+        parse_joint(body_pairs, options)
+    }).collect::<Result<Vec<Joint>, BvhError>>()?;
+    let children = if !joints.is_empty() {
         JointChildren::Joints(joints)
     } else {
         let mut end_site_pairs = joint_body_pairs.find(|pair| pair.as_rule() == Rule::end_site).unwrap().into_inner();
-        let mut offset_pairs = end_site_pairs.find(|pair| pair.as_rule() == Rule::offset).unwrap().into_inner();
-        let offset = parse_offset(&mut offset_pairs);
+        let offset_pairs = end_site_pairs.find(|pair| pair.as_rule() == Rule::offset).unwrap().into_inner();
+        let offset = parse_offset(offset_pairs, &name, options)?;
         JointChildren::EndSite(EndSite {
-            offset: offset,
+            offset,
         })
     };
-    Joint {
-        name: name,
-        offset: offset,
-        channels: channel_pairs.filter(|pair| pair.as_rule() == Rule::channel).map(|pair| match pair.as_str() {
-            "Xposition" => Channel::XPosition,
-            "Yposition" => Channel::YPosition,
-            "Zposition" => Channel::ZPosition,
-            "Xrotation" => Channel::XRotation,
-            "Yrotation" => Channel::YRotation,
-            "Zrotation" => Channel::ZRotation,
-            _ => unreachable!()
-        }).collect(),
-        children: children,
-    }
+    Ok(Joint {
+        name,
+        offset,
+        channels,
+        children,
+    })
 }
 
-fn parse_offset(offset_pairs: &mut Pairs<Rule>) -> Offset {
-    Offset {
-        x: parse_f64(offset_pairs),
-        y: parse_f64(offset_pairs),
-        z: parse_f64(offset_pairs),
+fn parse_offset(offset_pairs: Pairs<Rule>, joint_name: &str, options: &ParseOptions) -> Result<Offset, BvhError> {
+    let components: Vec<f64> = offset_pairs.filter(|pair| pair.as_rule() == Rule::float).map(|pair| pair.as_str().parse::<f64>().unwrap()).collect();
+    if components.len() != 3 && !options.lenient {
+        return Err(BvhError::BadOffset {
+            joint: joint_name.into(),
+            found: components.len(),
+        });
     }
+    let component = |index: usize| components.get(index).cloned().unwrap_or(0.0);
+    Ok(Offset {
+        x: component(0),
+        y: component(1),
+        z: component(2),
+    })
 }
 
 fn parse_f64(offset_pairs: &mut Pairs<Rule>) -> f64 {
@@ -163,8 +2074,12 @@ fn parse_f64(offset_pairs: &mut Pairs<Rule>) -> f64 {
 }
 
 pub fn serialize<W: Write>(bvh: &Bvh, w: &mut W) -> io::Result<()> {
+    serialize_with_options(bvh, &SerializeOptions::default(), w)
+}
+
+pub fn serialize_with_options<W: Write>(bvh: &Bvh, options: &SerializeOptions, w: &mut W) -> io::Result<()> {
     serialize_hierarchy(&bvh.hierarchy, w)?;
-    serialize_motion(&bvh.motion, w)?;
+    serialize_motion(&bvh.motion, options, w)?;
 
     Ok(())
 }
@@ -194,7 +2109,7 @@ fn serialize_joint<W: Write>(joint: &Joint, w: &mut W) -> io::Result<()> {
             Channel::ZRotation => write!(w, " Zrotation"),
         }?;
     }
-    writeln!(w, "")?;
+    writeln!(w)?;
 
     match joint.children {
         JointChildren::Joints(ref joints) => {
@@ -222,20 +2137,1259 @@ fn serialize_offset<W: Write>(offset: &Offset, w: &mut W) -> io::Result<()> {
     writeln!(w, "OFFSET {} {} {}", offset.x, offset.y, offset.z)
 }
 
-fn serialize_motion<W: Write>(motion: &Motion, w: &mut W) -> io::Result<()> {
+fn serialize_motion<W: Write>(motion: &Motion, options: &SerializeOptions, w: &mut W) -> io::Result<()> {
     writeln!(w, "MOTION")?;
     writeln!(w, "Frames: {}", motion.num_frames)?;
+    // `{}` writes the shortest decimal that parses back to the same f64 and never uses an exponent, so frame times like 1/120 survive
+    // a round trip exactly
     writeln!(w, "Frame Time: {}", motion.frame_time)?;
 
-    for frame in motion.frames.iter() {
+    for (frame_index, frame) in motion.frames.iter().enumerate() {
+        let mut line_width = 0;
         for (index, value) in frame.iter().enumerate() {
-            write!(w, "{}", value)?;
-            if index % frame.len() != frame.len() - 1 {
-                write!(w, " ")?;
+            let value = if options.integer_style == IntegerStyle::Decimal && value.is_finite() && value.fract() == 0.0 {
+                format!("{:.1}", value)
+            } else {
+                format!("{}", value)
+            };
+            if index > 0 {
+                // Frames are read by value count, so a frame can continue on further (indented) lines
+                if options.max_line_width.is_some_and(|max_line_width| line_width + 1 + value.len() > max_line_width) {
+                    write!(w, "\n{}", FRAME_CONTINUATION_INDENT)?;
+                    line_width = FRAME_CONTINUATION_INDENT.len();
+                } else {
+                    write!(w, " ")?;
+                    line_width += 1;
+                }
             }
+            write!(w, "{}", value)?;
+            line_width += value.len();
         }
-        writeln!(w, "")?;
+        if options.timestamp_comments {
+            write!(w, " # t={}", frame_index as f64 * motion.frame_time)?;
+        }
+        writeln!(w)?;
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Hips (6 channels) with two-bone arms on each side: columns 0..6 are Hips, 6..9 LeftArm, 9..12 LeftHand, 12..15 RightArm and
+    // 15..18 RightHand. Every rotation uses Zrotation Xrotation Yrotation order.
+    const SKELETON: &str = "HIERARCHY
+ROOT Hips
+{
+OFFSET 0 0 0
+CHANNELS 6 Xposition Yposition Zposition Zrotation Xrotation Yrotation
+JOINT LeftArm
+{
+OFFSET 1 2 0
+CHANNELS 3 Zrotation Xrotation Yrotation
+JOINT LeftHand
+{
+OFFSET 2 0 0
+CHANNELS 3 Zrotation Xrotation Yrotation
+End Site
+{
+OFFSET 1 0 0
+}
+}
+}
+JOINT RightArm
+{
+OFFSET -1 2 0
+CHANNELS 3 Zrotation Xrotation Yrotation
+JOINT RightHand
+{
+OFFSET -2 0 0
+CHANNELS 3 Zrotation Xrotation Yrotation
+End Site
+{
+OFFSET -1 0 0
+}
+}
+}
+}
+";
+    const CHANNELS: usize = 18;
+    const FRAME_TIME: f64 = 1.0 / 30.0;
+
+    fn source(motion: &str) -> String {
+        format!("{}MOTION\n{}", SKELETON, motion)
+    }
+
+    fn hierarchy() -> Hierarchy {
+        parse(&source("Frames: 1\nFrame Time: 0.1\n0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0\n")).unwrap().hierarchy
+    }
+
+    // A frame with every channel 0 except the given (column, value) pairs.
+    fn frame(values: &[(usize, f64)]) -> Vec<f64> {
+        let mut frame = vec![0.0; CHANNELS];
+        for &(column, value) in values.iter() {
+            frame[column] = value;
+        }
+        frame
+    }
+
+    fn to_string(bvh: &Bvh, options: &SerializeOptions) -> String {
+        let mut output = Vec::new();
+        serialize_with_options(bvh, options, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    fn close3(a: &[f64; 3], b: &[f64; 3]) -> bool {
+        (0..3).all(|axis| (a[axis] - b[axis]).abs() < 1e-6)
+    }
+
+    fn clip(frames: Vec<Vec<f64>>) -> Bvh {
+        Bvh {
+            hierarchy: hierarchy(),
+            motion: Motion {
+                num_frames: frames.len() as u32,
+                frame_time: FRAME_TIME,
+                frames,
+            },
+        }
+    }
+
+    #[test]
+    fn frames_for_duration_rounds_to_whole_frames() {
+        let motion = clip(vec![frame(&[])]).motion;
+        assert_eq!(motion.frames_for_duration(1.0), 30);
+        assert_eq!(motion.frames_for_duration(0.05), 2);
+        let stopped = Motion {
+            frame_time: 0.0,
+            ..motion
+        };
+        assert_eq!(stopped.frames_for_duration(1.0), 0);
+    }
+
+    #[test]
+    fn offsets_need_three_components_unless_lenient() {
+        let input = source("Frames: 1\nFrame Time: 0.1\n0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0\n").replace("OFFSET 1 2 0", "OFFSET 1 2");
+        match parse(&input) {
+            Err(BvhError::BadOffset { ref joint, found: 2 }) if joint == "LeftArm" => {}
+            other => panic!("expected a BadOffset error, got {:?}", other),
+        }
+        let options = ParseOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        let bvh = parse_with_options(&input, &options).unwrap();
+        assert_eq!(bvh.hierarchy.joints()[1].offset.to_array(), [1.0, 2.0, 0.0]);
+        assert!(parse(&input.replace("OFFSET 1 2\n", "OFFSET 1 2 0 4\n")).is_err());
+    }
+
+    #[test]
+    fn noisy_joints_flags_jittered_rotations() {
+        let bvh = clip((0..10).map(|index| {
+            let jitter = if index % 2 == 0 { 5.0 } else { -5.0 };
+            frame(&[(9, jitter), (15, index as f64 * 3.0)])
+        }).collect());
+        assert_eq!(bvh.noisy_joints(10.0), vec!["LeftHand".to_string()]);
+    }
+
+    #[cfg(feature = "ndarray")]
+    #[test]
+    fn ndarray_conversion_round_trips() {
+        let bvh = clip(vec![frame(&[(0, 1.5), (17, -2.0)]), frame(&[(6, 30.0)]), frame(&[])]);
+        let array = bvh.to_ndarray();
+        assert_eq!(array.shape(), &[3, CHANNELS]);
+        assert_eq!(array[[0, 0]], 1.5);
+        assert_eq!(array[[0, 17]], -2.0);
+        assert_eq!(array[[1, 6]], 30.0);
+        let rebuilt = Bvh::from_ndarray(hierarchy(), FRAME_TIME, &array).unwrap();
+        assert_eq!(rebuilt.motion.frames, bvh.motion.frames);
+        assert_eq!(rebuilt.motion.num_frames, 3);
+        let narrow = ndarray::Array2::zeros((2, CHANNELS - 1));
+        assert!(Bvh::from_ndarray(hierarchy(), FRAME_TIME, &narrow).is_err());
+    }
+
+    #[test]
+    fn fast_motion_frames_finds_the_fast_segment() {
+        let xs = [0.0, 0.0, 0.0, 0.0, 1.0, 2.0, 3.0, 4.0, 4.0, 4.0];
+        let bvh = clip(xs.iter().map(|&x| frame(&[(0, x)])).collect());
+        assert_eq!(bvh.fast_motion_frames("Hips", 20.0), vec![4, 5, 6]);
+        assert_eq!(bvh.fast_motion_frames("LeftHand", 20.0), vec![4, 5, 6]);
+        assert!(bvh.fast_motion_frames("Tail", 20.0).is_empty());
+    }
+
+    #[test]
+    fn solve_local_rotations_recovers_fk_rotations() {
+        let original = frame(&[(0, 1.0), (1, 2.0), (2, 3.0), (3, 10.0), (4, 20.0), (5, 30.0), (6, 30.0), (9, 15.0), (12, -20.0)]);
+        let hierarchy = hierarchy();
+        let positions = vec![hierarchy.world_positions(&original)];
+        let motion = hierarchy.solve_local_rotations(&positions).unwrap();
+        assert_eq!(motion.num_frames, 1);
+        // LeftHand and RightHand only have end sites, so they keep their parent's orientation and can't reproduce LeftHand's rotation
+        let expected = frame(&[(0, 1.0), (1, 2.0), (2, 3.0), (3, 10.0), (4, 20.0), (5, 30.0), (6, 30.0), (12, -20.0)]);
+        for (solved, expected) in motion.frames[0].iter().zip(expected.iter()) {
+            assert!((solved - expected).abs() < 1e-6, "{:?}", motion.frames[0]);
+        }
+        for (solved, original) in hierarchy.world_positions(&motion.frames[0]).iter().zip(positions[0].iter()) {
+            assert!(close3(solved, original));
+        }
+    }
+
+    #[test]
+    fn solve_local_rotations_cant_recover_twist_of_single_child_joints() {
+        // Xrotation of LeftArm twists it about its bone to LeftHand, which doesn't move any joint
+        let original = frame(&[(7, 40.0)]);
+        let hierarchy = hierarchy();
+        let positions = vec![hierarchy.world_positions(&original)];
+        let motion = hierarchy.solve_local_rotations(&positions).unwrap();
+        assert!(motion.frames[0].iter().all(|value| value.abs() < 1e-6));
+        for (solved, original) in hierarchy.world_positions(&motion.frames[0]).iter().zip(positions[0].iter()) {
+            assert!(close3(solved, original));
+        }
+    }
+
+    #[test]
+    fn solve_local_rotations_checks_joint_count() {
+        match hierarchy().solve_local_rotations(&[vec![[0.0; 3]; 2]]) {
+            Err(BvhError::JointCountMismatch { expected: 5, found: 2 }) => {}
+            other => panic!("expected a JointCountMismatch error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rest_bounding_sphere_encloses_a_symmetric_skeleton() {
+        let hierarchy = hierarchy();
+        let (center, radius) = hierarchy.rest_bounding_sphere();
+        assert!(close3(&center, &[0.0, 1.0, 0.0]));
+        assert!((radius - 10.0f64.sqrt()).abs() < 1e-9);
+        for position in hierarchy.rest_positions().iter() {
+            assert!(math::length(&math::sub(position, &center)) <= radius + 1e-9);
+        }
+    }
+
+    #[test]
+    fn swap_subtrees_swaps_hierarchy_and_columns() {
+        let original: Vec<f64> = (0..CHANNELS).map(|column| column as f64).collect();
+        let mut bvh = clip(vec![original.clone()]);
+        bvh.swap_subtrees("LeftArm", "RightArm").unwrap();
+        let names: Vec<&str> = bvh.hierarchy.joints().iter().map(|joint| joint.name.as_str()).collect();
+        assert_eq!(names, vec!["Hips", "RightArm", "RightHand", "LeftArm", "LeftHand"]);
+        let expected: Vec<f64> = original[..6].iter().chain(original[12..].iter()).chain(original[6..12].iter()).cloned().collect();
+        assert_eq!(bvh.motion.frames[0], expected);
+        match bvh.swap_subtrees("LeftArm", "LeftHand") {
+            Err(BvhError::NestedJoints { ref ancestor, ref descendant }) if ancestor == "LeftArm" && descendant == "LeftHand" => {}
+            other => panic!("expected a NestedJoints error, got {:?}", other),
+        }
+        assert!(bvh.swap_subtrees("LeftArm", "Tail").is_err());
+    }
+
+    #[test]
+    fn frame_time_in_fps_needs_lenient_parsing() {
+        let input = source("Frames: 1\nFrame Time: 30fps\n0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0\n");
+        let options = ParseOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        assert!((parse_with_options(&input, &options).unwrap().motion.frame_time - 1.0 / 30.0).abs() < 1e-12);
+        assert!(parse(&input).is_err());
+        assert!(parse_with_options(&input.replace("30fps", "0fps"), &options).is_err());
+        assert_eq!(parse_with_options(&input.replace("30fps", "0.04"), &options).unwrap().motion.frame_time, 0.04);
+    }
+
+    #[test]
+    fn joint_accelerations_are_constant_for_constant_acceleration() {
+        let bvh = clip((0..8).map(|index| frame(&[(0, (index * index) as f64)])).collect());
+        let expected = 2.0 / (FRAME_TIME * FRAME_TIME);
+        for accelerations in bvh.joint_accelerations().iter() {
+            assert_eq!(accelerations.len(), 5);
+            for acceleration in accelerations.iter() {
+                assert!(close3(&math::scale(acceleration, 1.0 / expected), &[1.0, 0.0, 0.0]));
+            }
+        }
+    }
+
+    #[test]
+    fn trim_static_ends_keeps_the_active_range() {
+        let xs = [0.0, 0.0, 0.0, 0.0, 1.0, 2.0, 3.0, 3.0, 3.0, 3.0];
+        let mut bvh = clip(xs.iter().map(|&x| frame(&[(0, x)])).collect());
+        bvh.trim_static_ends(1e-6);
+        let trimmed: Vec<f64> = bvh.motion.frames.iter().map(|frame| frame[0]).collect();
+        assert_eq!(trimmed, vec![0.0, 1.0, 2.0, 3.0]);
+        assert_eq!(bvh.motion.num_frames, 4);
+
+        let mut still = clip(vec![frame(&[]); 5]);
+        still.trim_static_ends(1e-6);
+        assert_eq!(still.motion.frames.len(), 1);
+        assert_eq!(still.motion.num_frames, 1);
+
+        let mut negative = clip(xs.iter().map(|&x| frame(&[(0, x)])).collect());
+        negative.trim_static_ends(-1.0);
+        assert_eq!(negative.motion.frames.len(), xs.len());
+    }
+
+    #[test]
+    fn validate_warns_about_roots_without_translation() {
+        let hierarchy = hierarchy();
+        assert!(hierarchy.root_has_translation());
+        assert!(hierarchy.validate().is_empty());
+        let rotation_only = parse_hierarchy_only(&SKELETON.replace("CHANNELS 6 Xposition Yposition Zposition Zrotation", "CHANNELS 3 Zrotation")).unwrap();
+        assert!(!rotation_only.root_has_translation());
+        assert_eq!(rotation_only.validate().len(), 1);
+    }
+
+    #[test]
+    fn pose_at_phase_wraps_around_a_loop() {
+        let xs = [0.0, 1.0, 2.0, 1.0, 0.0];
+        let bvh = clip(xs.iter().map(|&x| frame(&[(0, x)])).collect());
+        assert_eq!(bvh.pose_at_phase(0.0), bvh.pose_at_phase(1.0));
+        assert!((bvh.pose_at_phase(0.5)[0] - 2.0).abs() < 1e-9);
+        assert!((bvh.pose_at_phase(1.25)[0] - 1.0).abs() < 1e-9);
+        assert!((bvh.pose_at_phase(-0.75)[0] - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn huge_frame_counts_are_rejected_before_allocating() {
+        let input = source("Frames: 4000000000\nFrame Time: 0.1\n0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0\n");
+        match parse(&input) {
+            Err(BvhError::FrameCountMismatch { declared: 4000000000, found: 1 }) => {}
+            other => panic!("expected a FrameCountMismatch error, got {:?}", other.map(|bvh| bvh.motion.num_frames)),
+        }
+        let options = ParseOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        let bvh = parse_with_options(&input, &options).unwrap();
+        assert_eq!(bvh.motion.num_frames, 1);
+        assert_eq!(bvh.motion.frames.len(), 1);
+        assert!(parse(&input.replace("4000000000", "99999999999")).is_err());
+
+        let short = parse(&input.replace("4000000000", "3")).unwrap();
+        assert_eq!(short.motion.num_frames, 3);
+        assert_eq!(short.motion.frames.len(), 1);
+    }
+
+    #[test]
+    fn empty_motion_round_trips() {
+        let empty = clip(Vec::new());
+        let parsed = parse(&to_string(&empty, &SerializeOptions::default())).unwrap();
+        assert_eq!(parsed.motion.num_frames, 0);
+        assert!(parsed.motion.frames.is_empty());
+        assert!(empty.check_roundtrip().is_ok());
+    }
+
+    #[test]
+    fn normalized_offsets_dont_depend_on_scale() {
+        fn scale_offset(offset: &mut Offset, factor: f64) {
+            offset.x *= factor;
+            offset.y *= factor;
+            offset.z *= factor;
+        }
+        fn scale_joint(joint: &mut Joint, factor: f64) {
+            scale_offset(&mut joint.offset, factor);
+            match joint.children {
+                JointChildren::Joints(ref mut children) => children.iter_mut().for_each(|child| scale_joint(child, factor)),
+                JointChildren::EndSite(ref mut end_site) => scale_offset(&mut end_site.offset, factor),
+            }
+        }
+        let original = hierarchy();
+        let mut scaled = original.clone();
+        scale_joint(&mut scaled.root, 3.5);
+        assert!((scaled.total_bone_length() - 3.5 * original.total_bone_length()).abs() < 1e-9);
+        for ((name, offset), (scaled_name, scaled_offset)) in original.normalized_offsets().iter().zip(scaled.normalized_offsets().iter()) {
+            assert_eq!(name, scaled_name);
+            assert!(close3(offset, scaled_offset));
+        }
+        assert!(close3(&original.normalized_offsets()[2].1, &[2.0 / (6.0 + 2.0 * 5.0f64.sqrt()), 0.0, 0.0]));
+    }
+
+    // A few frames moving every channel, for tests that need arbitrary motion.
+    fn busy_clip() -> Bvh {
+        clip((0..4).map(|index| (0..CHANNELS).map(|column| ((index * CHANNELS + column) as f64 * 7.3) % 50.0 - 25.0).collect()).collect())
+    }
+
+    #[test]
+    fn reroot_preserves_world_positions() {
+        let bvh = busy_clip();
+        for new_root in ["LeftArm", "LeftHand", "RightArm", "Hips"].iter() {
+            let rerooted = bvh.reroot(new_root).unwrap();
+            assert_eq!(rerooted.hierarchy.root.name, *new_root);
+            assert_eq!(rerooted.motion.frames[0].len(), rerooted.hierarchy.root.total_channels() as usize);
+            let names: Vec<String> = rerooted.hierarchy.joints().iter().map(|joint| joint.name.clone()).collect();
+            for (original, rerooted_frame) in bvh.motion.frames.iter().zip(rerooted.motion.frames.iter()) {
+                let original_positions = bvh.hierarchy.world_positions(original);
+                let rerooted_positions = rerooted.hierarchy.world_positions(rerooted_frame);
+                for (name, position) in names.iter().zip(rerooted_positions.iter()) {
+                    assert!(close3(position, &original_positions[bvh.hierarchy.joint_index(name).unwrap()]), "{} after rerooting at {}", name, new_root);
+                }
+            }
+        }
+        assert!(bvh.reroot("Tail").is_err());
+    }
+
+    #[test]
+    fn to_dot_has_an_edge_per_bone() {
+        let dot = hierarchy().to_dot();
+        assert!(dot.starts_with("digraph skeleton {\n"));
+        assert_eq!(dot.lines().filter(|line| line.contains("->")).count(), 6);
+        assert_eq!(dot.lines().filter(|line| line.contains("[label=")).count(), 7);
+        assert!(dot.contains("joint1 [label=\"LeftArm\"];"));
+        assert!(dot.contains("joint0 -> joint1;"));
+    }
+
+    #[test]
+    fn resample_can_interpolate_positions_with_catmull_rom() {
+        let bvh = clip((0..5).map(|index| frame(&[(0, (index * index) as f64), (3, (index * index) as f64)])).collect());
+        let layout = bvh.hierarchy.channel_layout();
+        let linear = bvh.motion.resample(FRAME_TIME / 2.0, &layout, Interpolation::Linear);
+        let cubic = bvh.motion.resample(FRAME_TIME / 2.0, &layout, Interpolation::CatmullRom);
+        assert_eq!(linear.frames.len(), 9);
+        assert_eq!(cubic.frames.len(), 9);
+        assert_eq!(cubic.frame_time, FRAME_TIME / 2.0);
+        // Halfway between frames 1 and 2 the path x = frame² is at 2.25, which Catmull-Rom reproduces and a straight line misses
+        assert!((linear.frames[3][0] - 2.5).abs() < 1e-9);
+        assert!((cubic.frames[3][0] - 2.25).abs() < 1e-9);
+        // Rotations are always linear
+        assert!((cubic.frames[3][3] - 2.5).abs() < 1e-9);
+        assert_eq!(cubic.frames[4], bvh.motion.frames[2]);
+    }
+
+    #[test]
+    fn ground_footprint_spans_the_walk() {
+        let bvh = clip((0..11).map(|index| frame(&[(0, index as f64), (2, (index % 2) as f64 * 0.5)])).collect());
+        let footprint = bvh.ground_footprint();
+        assert!(footprint.len() >= 4);
+        let min_x = footprint.iter().map(|point| point[0]).fold(f64::INFINITY, f64::min);
+        let max_x = footprint.iter().map(|point| point[0]).fold(f64::NEG_INFINITY, f64::max);
+        // Hands reach 3 either side of the root, which travels from 0 to 10
+        assert!((min_x + 3.0).abs() < 1e-9);
+        assert!((max_x - 13.0).abs() < 1e-9);
+        assert!(footprint.iter().all(|point| point[1] >= -1e-9 && point[1] <= 0.5 + 1e-9));
+    }
+
+    #[test]
+    fn bake_global_transform_shifts_root_positions() {
+        let mut bvh = busy_clip();
+        let original = bvh.clone();
+        let mut translation = math::matrix4_identity();
+        translation[0][3] = 5.0;
+        translation[2][3] = -2.0;
+        bvh.bake_global_transform(translation).unwrap();
+        for (baked, original) in bvh.motion.frames.iter().zip(original.motion.frames.iter()) {
+            assert!(close3(&[baked[0], baked[1], baked[2]], &[original[0] + 5.0, original[1], original[2] - 2.0]));
+            assert!(baked[3..].iter().zip(original[3..].iter()).all(|(a, b)| (a - b).abs() < 1e-6));
+        }
+
+        let mut rotation_only = clip(vec![frame(&[])]);
+        rotation_only.hierarchy.root.channels = vec![Channel::ZRotation, Channel::XRotation, Channel::YRotation];
+        rotation_only.motion.frames[0].truncate(CHANNELS - 3);
+        assert!(rotation_only.bake_global_transform(translation).is_err());
+    }
+
+    #[test]
+    fn channel_ranges_follow_the_layout() {
+        let bvh = clip((0..5).map(|index| frame(&[(1, index as f64 * 2.0), (7, 1.0 + index as f64 * 1e-7)])).collect());
+        let ranges = bvh.channel_ranges();
+        assert_eq!(ranges.len(), CHANNELS);
+        assert_eq!((ranges[1].0.as_str(), ranges[1].1), ("Hips", Channel::YPosition));
+        assert!((ranges[1].2 - 8.0).abs() < 1e-9);
+        assert_eq!((ranges[7].0.as_str(), ranges[7].1), ("LeftArm", Channel::XRotation));
+        assert!(ranges[7].2 < 1e-6);
+        assert_eq!(ranges[0].2, 0.0);
+    }
+
+    #[test]
+    fn end_site_keyword_variants_parse() {
+        let input = source("Frames: 1\nFrame Time: 0.1\n0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0\n");
+        for keyword in ["End Site", "ENDSITE", "End_Site", "end site", "EndSite"].iter() {
+            let bvh = parse(&input.replace("End Site", keyword)).unwrap();
+            match bvh.hierarchy.joints()[2].children {
+                JointChildren::EndSite(ref end_site) => assert_eq!(end_site.offset.to_array(), [1.0, 0.0, 0.0]),
+                JointChildren::Joints(_) => panic!("{} wasn't parsed as an end site", keyword),
+            }
+            let serialized = to_string(&bvh, &SerializeOptions::default());
+            assert_eq!(serialized.matches("End Site\n").count(), 2);
+        }
+    }
+
+    #[test]
+    fn content_hash_ignores_differences_below_the_quantum() {
+        let bvh = busy_clip();
+        let mut nudged = bvh.clone();
+        nudged.motion.frames[1][4] += 1e-6;
+        let mut moved = bvh.clone();
+        moved.motion.frames[1][4] += 0.1;
+        assert_eq!(bvh.content_hash(), nudged.content_hash());
+        assert_ne!(bvh.content_hash(), moved.content_hash());
+        let mut renamed = bvh.clone();
+        renamed.hierarchy.root.name = "Pelvis".into();
+        assert_ne!(bvh.content_hash(), renamed.content_hash());
+    }
+
+    #[test]
+    fn fnv1a_matches_the_reference() {
+        let mut hasher = Fnv1a::new();
+        assert_eq!(hasher.finish(), 0xcbf2_9ce4_8422_2325);
+        hasher.write(b"a");
+        assert_eq!(hasher.finish(), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn joint_transform_at_interpolates_between_frames() {
+        let bvh = clip(vec![frame(&[]), frame(&[(0, 2.0), (6, 90.0)])]);
+        let transform = bvh.joint_transform_at("LeftHand", FRAME_TIME * 0.5).unwrap();
+        let half = 2.0f64.sqrt();
+        assert!(close3(&math::matrix4_translation(&transform), &[2.0 + half, 2.0 + half, 0.0]));
+        assert!(close3(&math::matrix4_rotation(&transform)[0], &[half / 2.0, -half / 2.0, 0.0]));
+        assert!(bvh.joint_transform_at("Tail", 0.0).is_none());
+        assert!(clip(Vec::new()).joint_transform_at("LeftHand", 0.0).is_none());
+    }
+
+    #[test]
+    fn windows_drop_incomplete_trailing_windows() {
+        let motion = clip((0..100).map(|index| frame(&[(0, index as f64)])).collect()).motion;
+        let windows = motion.windows(30, 10);
+        assert_eq!(windows.len(), 8);
+        assert!(windows.iter().all(|window| window.frames.len() == 30 && window.num_frames == 30 && window.frame_time == FRAME_TIME));
+        assert_eq!(windows[7].frames[0][0], 70.0);
+        assert_eq!(windows[7].frames[29][0], 99.0);
+        assert_eq!(motion.windows(30, 40).len(), 2);
+        assert!(motion.windows(101, 1).is_empty());
+    }
+
+    #[test]
+    fn rotations_relative_to_rest_are_zero_at_rest() {
+        let bvh = clip(vec![frame(&[(0, 4.0)]), frame(&[(6, 30.0)])]);
+        let rotations = bvh.local_rotations_relative_to_rest();
+        assert!(rotations[0].iter().all(|rotation| close3(rotation, &[0.0; 3])));
+        assert!(close3(&rotations[1][1], &[0.0, 0.0, 30.0]));
+        assert!(close3(&rotations[1][2], &[0.0; 3]));
+    }
+
+    #[test]
+    fn dedupe_consecutive_collapses_a_pause() {
+        let xs: Vec<f64> = (0..5).map(|x| x as f64).chain((0..10).map(|_| 5.0)).chain((6..9).map(|x| x as f64)).collect();
+        let mut motion = clip(xs.iter().map(|&x| frame(&[(0, x)])).collect()).motion;
+        let retained = motion.dedupe_consecutive(1e-6);
+        assert_eq!(retained, vec![0, 1, 2, 3, 4, 5, 15, 16, 17]);
+        assert_eq!(motion.num_frames, 9);
+        let values: Vec<f64> = motion.frames.iter().map(|frame| frame[0]).collect();
+        assert_eq!(values, vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0]);
+    }
+
+    #[test]
+    fn from_pose_repeats_the_pose() {
+        let pose = frame(&[(1, 90.0), (6, 45.0)]);
+        let bvh = Bvh::from_pose(hierarchy(), &pose, 12, FRAME_TIME).unwrap();
+        assert_eq!(bvh.motion.num_frames, 12);
+        assert_eq!(bvh.motion.frames.len(), 12);
+        assert!(bvh.motion.frames.iter().all(|frame| *frame == pose));
+        match Bvh::from_pose(hierarchy(), &pose[1..], 12, FRAME_TIME) {
+            Err(BvhError::ChannelCountMismatch { expected: CHANNELS, found: 17 }) => {}
+            other => panic!("expected a ChannelCountMismatch error, got {:?}", other.map(|bvh| bvh.motion.num_frames)),
+        }
+    }
+
+    #[test]
+    fn twist_swing_separates_twist_about_the_bone() {
+        let bvh = clip(vec![frame(&[(7, 40.0)]), frame(&[(6, 30.0)])]);
+        let decomposed = bvh.twist_swing("LeftArm", [2.0, 0.0, 0.0]).unwrap();
+        let (twist, swing) = decomposed[0];
+        assert!((twist - 40.0).abs() < 1e-9);
+        assert!((swing[3] - 1.0).abs() < 1e-9 && close3(&[swing[0], swing[1], swing[2]], &[0.0; 3]));
+        let (twist, swing) = decomposed[1];
+        assert!(twist.abs() < 1e-9);
+        assert!(close3(&math::quaternion_to_rotation_vector(&swing), &[0.0, 0.0, 30.0]));
+        assert!(bvh.twist_swing("LeftArm", [0.0; 3]).is_none());
+        assert!(bvh.twist_swing("Tail", [1.0, 0.0, 0.0]).is_none());
+    }
+
+    #[test]
+    fn dominant_travel_direction_follows_the_root() {
+        let forward = clip((0..10).map(|index| frame(&[(0, index as f64), (2, (index % 2) as f64 * 0.1)])).collect());
+        let direction = forward.dominant_travel_direction();
+        assert!(math::dot(&direction, &[1.0, 0.0, 0.0]) > 0.999);
+        assert!((math::length(&direction) - 1.0).abs() < 1e-9);
+        // Out along Z and back again: the net displacement is tiny, so the principal axis is used
+        let there_and_back = clip([0.0, 1.0, 2.0, 3.0, 2.0, 1.0, 0.1].iter().map(|&z| frame(&[(2, z)])).collect());
+        assert!(math::dot(&there_and_back.dominant_travel_direction(), &[0.0, 0.0, 1.0]) > 0.999);
+        assert_eq!(clip(vec![frame(&[]); 3]).dominant_travel_direction(), [0.0; 3]);
+    }
+
+    #[test]
+    fn validate_takes_reports_the_mismatched_take() {
+        let hierarchy = hierarchy();
+        let good = clip(vec![frame(&[]); 3]).motion;
+        let mut bad = good.clone();
+        bad.frames[1].pop();
+        assert!(validate_takes(&hierarchy, &[good.clone(), good.clone()]).is_ok());
+        match validate_takes(&hierarchy, &[good.clone(), bad, good]) {
+            Err(BvhError::TakeChannelMismatch { take: 1, expected: CHANNELS, found: 17 }) => {}
+            other => panic!("expected a TakeChannelMismatch error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn joint_angle_measures_the_bend() {
+        let bvh = clip(vec![frame(&[]), frame(&[(6, 45.0)]), frame(&[(6, 90.0)])]);
+        let angles = bvh.joint_angle("RightArm", "LeftArm", "LeftHand").unwrap();
+        assert_eq!(angles.len(), 3);
+        assert!((angles[0] - 180.0).abs() < 1e-6);
+        assert!((angles[1] - 135.0).abs() < 1e-6);
+        assert!((angles[2] - 90.0).abs() < 1e-6);
+        assert!(bvh.joint_angle("RightArm", "Elbow", "LeftHand").is_none());
+    }
+
+    #[test]
+    fn binary_round_trip_keeps_values_within_f32_precision() {
+        let bvh = busy_clip();
+        let mut bytes = Vec::new();
+        bvh.write_binary(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 3 * 4 + 8 + 4 * CHANNELS * 4);
+        let read = Bvh::read_binary(hierarchy(), &mut &bytes[..]).unwrap();
+        assert_eq!(read.motion.num_frames, 4);
+        assert_eq!(read.motion.frame_time, FRAME_TIME);
+        for (read_frame, frame) in read.motion.frames.iter().zip(bvh.motion.frames.iter()) {
+            assert!(read_frame.iter().zip(frame.iter()).all(|(a, b)| (a - b).abs() < 1e-5));
+        }
+        let mut other = hierarchy();
+        other.root.channels.pop();
+        assert!(Bvh::read_binary(other, &mut &bytes[..]).is_err());
+        assert!(Bvh::read_binary(hierarchy(), &mut &bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn facing_directions_rotate_with_the_hips() {
+        let bvh = clip(vec![frame(&[]), frame(&[(5, 90.0)]), frame(&[(5, 180.0)])]);
+        let directions = bvh.facing_directions("Hips");
+        assert!(close3(&directions[0], &[0.0, 0.0, 1.0]));
+        assert!(close3(&directions[1], &[1.0, 0.0, 0.0]));
+        assert!(close3(&directions[2], &[0.0, 0.0, -1.0]));
+        assert!(bvh.facing_directions("Tail").is_empty());
+    }
+
+    #[test]
+    fn phase_shift_wraps_around() {
+        let motion = clip((0..20).map(|index| frame(&[(0, index as f64)])).collect()).motion;
+        let shifted = motion.phase_shift(10);
+        assert_eq!(shifted.frames.len(), 20);
+        assert_eq!(shifted.frames[0], motion.frames[10]);
+        assert_eq!(shifted.frames[10], motion.frames[0]);
+        let backward = motion.phase_shift(-3);
+        assert_eq!(backward.frames[0], motion.frames[17]);
+        assert_eq!(motion.phase_shift(25).frames[0], motion.frames[5]);
+    }
+
+    #[test]
+    fn estimate_ground_height_finds_the_lowest_foot() {
+        let bvh = clip(vec![frame(&[]), frame(&[(1, 0.5), (6, -90.0)]), frame(&[(1, -1.0)])]);
+        assert!((bvh.estimate_ground_height(&["LeftHand", "RightHand"]) - 0.5).abs() < 1e-9);
+        assert!((bvh.estimate_ground_height(&["RightHand", "Tail"]) - 1.0).abs() < 1e-9);
+        assert_eq!(bvh.estimate_ground_height(&["Tail"]), f64::INFINITY);
+    }
+
+    #[test]
+    fn ground_to_floor_puts_the_lowest_foot_on_the_floor() {
+        let mut bvh = clip(vec![frame(&[]), frame(&[(1, 0.5), (6, -90.0)]), frame(&[(1, -1.0)])]);
+        let feet = ["LeftHand", "RightHand"];
+        bvh.ground_to_floor(&feet, -3.0);
+        assert!((bvh.estimate_ground_height(&feet) + 3.0).abs() < 1e-9);
+        assert!((bvh.motion.frames[0][1] + 3.5).abs() < 1e-9);
+        let before = bvh.motion.frames.clone();
+        bvh.ground_to_floor(&["Tail"], 0.0);
+        assert_eq!(bvh.motion.frames, before);
+    }
+
+    #[test]
+    fn kinetic_energy_proxy_is_higher_when_moving_fast() {
+        let xs = [0.0, 0.1, 0.2, 0.3, 0.4, 1.4, 2.4, 3.4, 4.4];
+        let bvh = clip(xs.iter().map(|&x| frame(&[(0, x)])).collect());
+        let energy = bvh.kinetic_energy_proxy();
+        assert_eq!(energy.len(), xs.len());
+        // Every joint moves with the root at 3 units per second in the slow part
+        assert!((energy[2] - 5.0 * 9.0).abs() < 1e-6);
+        assert!(energy[6] > 50.0 * energy[2]);
+    }
+
+    #[test]
+    fn decode_frame_splits_positions_and_rotations() {
+        let poses = hierarchy().decode_frame(&frame(&[(0, 1.0), (1, 2.0), (2, 3.0), (3, 30.0), (4, 10.0), (5, 20.0), (6, 5.0), (7, 6.0), (8, 7.0)])).unwrap();
+        assert_eq!(poses.len(), 5);
+        assert_eq!(poses[0].0, "Hips");
+        assert_eq!(poses[0].1, JointPose {
+            position: Some([1.0, 2.0, 3.0]),
+            rotation: Some([10.0, 20.0, 30.0]),
+        });
+        assert_eq!(poses[1].0, "LeftArm");
+        assert_eq!(poses[1].1, JointPose {
+            position: None,
+            rotation: Some([6.0, 7.0, 5.0]),
+        });
+        match hierarchy().decode_frame(&[0.0; 9]) {
+            Err(BvhError::ChannelCountMismatch { expected: CHANNELS, found: 9 }) => {}
+            other => panic!("expected a ChannelCountMismatch error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn encode_frame_inverts_decode_frame() {
+        let hierarchy = hierarchy();
+        let original = busy_clip().motion.frames[2].clone();
+        let mut poses = hierarchy.decode_frame(&original).unwrap();
+        assert_eq!(hierarchy.encode_frame(&poses).unwrap(), original);
+
+        poses.reverse();
+        assert_eq!(hierarchy.encode_frame(&poses).unwrap(), original);
+        poses[0].1.position = Some([0.0; 3]);
+        match hierarchy.encode_frame(&poses) {
+            Err(BvhError::PoseChannelMismatch { ref joint }) if joint == &poses[0].0 => {}
+            other => panic!("expected a PoseChannelMismatch error, got {:?}", other),
+        }
+        let (removed, _) = poses.remove(0);
+        match hierarchy.encode_frame(&poses) {
+            Err(BvhError::MissingPose(ref joint)) if *joint == removed => {}
+            other => panic!("expected a MissingPose error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn detect_period_finds_the_loop_length() {
+        let periodic = clip((0..60).map(|index| {
+            let angle = index as f64 * 2.0 * ::std::f64::consts::PI / 12.0;
+            frame(&[(6, 30.0 * angle.sin()), (9, 10.0 * angle.cos())])
+        }).collect());
+        assert_eq!(periodic.detect_period(2), Some(12));
+        let mut seed: u64 = 1;
+        let noise = clip((0..60).map(|_| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            frame(&[(6, (seed >> 33) as f64 / (1u64 << 31) as f64)])
+        }).collect());
+        assert_eq!(noise.detect_period(2), None);
+    }
+
+    #[test]
+    fn symmetry_error_is_zero_for_mirrored_poses() {
+        let bvh = clip(vec![frame(&[(6, 30.0), (7, 10.0), (12, -30.0), (13, 10.0)]), frame(&[(6, 30.0), (7, 10.0), (13, 10.0)])]);
+        let errors = bvh.symmetry_error(&[("LeftArm", "RightArm"), ("LeftHand", "RightHand"), ("LeftArm", "Tail")]);
+        assert!(errors[0].abs() < 1e-9);
+        assert!((errors[1] - 30.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn channel_aliases_map_custom_names() {
+        let input = source("Frames: 1\nFrame Time: 0.1\n0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0\n").replace("CHANNELS 3 Zrotation Xrotation Yrotation", "CHANNELS 3 rotZ rotX rotY");
+        assert!(parse(&input).is_err());
+        let mut options = ParseOptions::default();
+        options.channel_aliases.insert("rotX".into(), Channel::XRotation);
+        options.channel_aliases.insert("rotY".into(), Channel::YRotation);
+        options.channel_aliases.insert("rotZ".into(), Channel::ZRotation);
+        let bvh = parse_with_options(&input, &options).unwrap();
+        assert_eq!(bvh.hierarchy.channel_layout(), hierarchy().channel_layout());
+    }
+
+    #[test]
+    fn split_pos_rot_partitions_the_columns() {
+        let bvh = busy_clip();
+        let (positions, rotations) = bvh.split_pos_rot();
+        assert_eq!(positions.frames[0].len(), 3);
+        assert_eq!(rotations.frames[0].len(), CHANNELS - 3);
+        for ((frame, positions), rotations) in bvh.motion.frames.iter().zip(positions.frames.iter()).zip(rotations.frames.iter()) {
+            assert_eq!(positions[..], frame[..3]);
+            assert_eq!(rotations[..], frame[3..]);
+        }
+        assert_eq!(positions.num_frames, bvh.motion.num_frames);
+        assert_eq!(rotations.frame_time, FRAME_TIME);
+    }
+
+    #[test]
+    fn cumulative_rotation_unwraps_full_turns() {
+        // Two full turns about Y in 30 degree steps, stored wrapped to [-180, 180) as exporters do
+        let bvh = clip((0..25).map(|index| frame(&[(8, ((index * 30 + 180) % 360) as f64 - 180.0), (6, if index % 2 == 0 { 5.0 } else { 0.0 })])).collect());
+        let totals = bvh.cumulative_rotation("LeftArm").unwrap();
+        assert!(close3(&totals, &[0.0, 720.0, 120.0]));
+        assert!(bvh.cumulative_rotation("Tail").is_none());
+    }
+
+    fn frame_time_round_trip(frame_time: f64) {
+        let mut bvh = clip(vec![frame(&[])]);
+        bvh.motion.frame_time = frame_time;
+        let serialized = to_string(&bvh, &SerializeOptions::default());
+        let parsed = parse(&serialized).unwrap();
+        assert_eq!(parsed.motion.frame_time, frame_time);
+        assert_eq!(to_string(&parsed, &SerializeOptions::default()), serialized);
+    }
+
+    #[test]
+    fn frame_time_of_1_120_round_trips() {
+        frame_time_round_trip(1.0 / 120.0);
+    }
+
+    #[test]
+    fn frame_time_of_1_240_round_trips() {
+        frame_time_round_trip(1.0 / 240.0);
+    }
+
+    #[test]
+    fn effector_contribution_ranks_the_shoulder_first() {
+        let bvh = clip(vec![frame(&[(3, 5.0), (6, 90.0)]), frame(&[(3, -5.0), (6, 60.0)])]);
+        let contributions = bvh.effector_contribution("LeftHand");
+        let names: Vec<&str> = contributions.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["Hips", "LeftArm"]);
+        assert!(contributions.iter().all(|&(_, contribution)| contribution > 0.0));
+        assert!(contributions[1].1 > 3.0 * contributions[0].1);
+        assert!(bvh.effector_contribution("Tail").is_empty());
+    }
+
+    #[test]
+    fn position_sequence_matches_world_positions() {
+        let bvh = busy_clip();
+        let positions = bvh.to_position_sequence();
+        assert_eq!(positions.len(), 4);
+        assert!(positions.iter().all(|frame_positions| frame_positions.len() == 5));
+        assert_eq!(positions[2], bvh.hierarchy.world_positions(&bvh.motion.frames[2]));
+    }
+
+    // Lines after the Frame Time line, i.e. the serialized frame values.
+    fn frame_lines(serialized: &str) -> Vec<String> {
+        serialized.lines().skip_while(|line| !line.starts_with("Frame Time:")).skip(1).map(String::from).collect()
+    }
+
+    #[test]
+    fn integer_style_controls_whole_number_formatting() {
+        let bvh = clip(vec![frame(&[(0, 10.0), (1, 0.5)])]);
+        let bare = to_string(&bvh, &SerializeOptions::default());
+        assert_eq!(frame_lines(&bare), vec![format!("10 0.5{}", " 0".repeat(CHANNELS - 2))]);
+        let options = SerializeOptions {
+            integer_style: IntegerStyle::Decimal,
+            ..Default::default()
+        };
+        let decimal = to_string(&bvh, &options);
+        assert_eq!(frame_lines(&decimal), vec![format!("10.0 0.5{}", " 0.0".repeat(CHANNELS - 2))]);
+        assert_eq!(parse(&decimal).unwrap().motion.frames, bvh.motion.frames);
+    }
+
+    #[test]
+    fn rest_segments_cover_every_bone() {
+        let segments = hierarchy().rest_segments();
+        assert_eq!(segments.len(), 5 + 2 - 1);
+        assert!(segments.contains(&([0.0, 0.0, 0.0], [1.0, 2.0, 0.0])));
+        assert!(segments.contains(&([1.0, 2.0, 0.0], [3.0, 2.0, 0.0])));
+        assert!(segments.contains(&([3.0, 2.0, 0.0], [4.0, 2.0, 0.0])));
+        assert!(segments.contains(&([-3.0, 2.0, 0.0], [-4.0, 2.0, 0.0])));
+    }
+
+    #[test]
+    fn frame_index_at_time_rounds_and_clamps() {
+        let motion = clip(vec![frame(&[]); 10]).motion;
+        assert_eq!(motion.frame_index_at_time(FRAME_TIME * 2.4), 2);
+        assert_eq!(motion.frame_index_at_time(FRAME_TIME * 2.6), 3);
+        assert_eq!(motion.frame_index_at_time(-1.0), 0);
+        assert_eq!(motion.frame_index_at_time(100.0), 9);
+        assert_eq!(clip(Vec::new()).motion.frame_index_at_time(1.0), 0);
+    }
+
+    #[test]
+    fn repair_renames_duplicates_and_closes_leaves() {
+        let mut bvh = clip(vec![frame(&[])]);
+        if let JointChildren::Joints(ref mut children) = bvh.hierarchy.root.children {
+            let right_arm = &mut children[1];
+            right_arm.name = "LeftArm".into();
+            if let JointChildren::Joints(ref mut children) = right_arm.children {
+                children[0].name = "LeftArm2".into();
+                children[0].children = JointChildren::Joints(Vec::new());
+            }
+        }
+        let repairs = bvh.hierarchy.repair();
+        assert_eq!(repairs.len(), 2);
+        let names: Vec<String> = bvh.hierarchy.joints().iter().map(|joint| joint.name.clone()).collect();
+        assert_eq!(names, vec!["Hips", "LeftArm", "LeftHand", "LeftArm3", "LeftArm2"]);
+        let reparsed = parse(&to_string(&bvh, &SerializeOptions::default())).unwrap();
+        let reparsed_names: Vec<String> = reparsed.hierarchy.joints().iter().map(|joint| joint.name.clone()).collect();
+        assert_eq!(reparsed_names, names);
+    }
+
+    #[test]
+    fn local_positions_add_position_channels_to_offsets() {
+        let bvh = clip(vec![frame(&[])]);
+        let positions = bvh.local_positions(&frame(&[(0, 1.0), (1, 2.0), (2, 3.0), (6, 45.0)])).unwrap();
+        assert_eq!(positions[0], ("Hips".to_string(), [1.0, 2.0, 3.0]));
+        assert_eq!(positions[1], ("LeftArm".to_string(), [1.0, 2.0, 0.0]));
+        assert_eq!(positions[2], ("LeftHand".to_string(), [2.0, 0.0, 0.0]));
+    }
+
+    #[test]
+    fn reorder_columns_permutes_and_rejects_bad_permutations() {
+        let mut motion = Motion {
+            num_frames: 2,
+            frame_time: FRAME_TIME,
+            frames: vec![vec![1.0, 2.0, 3.0], vec![4.0, 5.0, 6.0]],
+        };
+        motion.reorder_columns(&[2, 0, 1]).unwrap();
+        assert_eq!(motion.frames, vec![vec![3.0, 1.0, 2.0], vec![6.0, 4.0, 5.0]]);
+        assert!(motion.reorder_columns(&[0, 0, 1]).is_err());
+        assert!(motion.reorder_columns(&[0, 1, 3]).is_err());
+        assert!(motion.reorder_columns(&[1, 0]).is_err());
+        assert_eq!(motion.frames, vec![vec![3.0, 1.0, 2.0], vec![6.0, 4.0, 5.0]]);
+    }
+
+    #[test]
+    fn active_range_trims_static_padding() {
+        let xs = [0.0, 0.0, 0.0, 1.0, 2.0, 3.0, 3.0, 3.0];
+        let bvh = clip(xs.iter().map(|&x| frame(&[(0, x)])).collect());
+        assert_eq!(bvh.active_range(1e-9), (3, 7));
+        assert_eq!(bvh.active_range(-1.0), (0, 7));
+        assert_eq!(clip(vec![frame(&[]); 4]).active_range(1e-9), (0, 0));
+        assert_eq!(clip(Vec::new()).active_range(1e-9), (0, 0));
+    }
+
+    #[test]
+    fn ease_to_pose_ends_on_target() {
+        let mut bvh = clip(vec![frame(&[]); 6]);
+        let target = frame(&[(0, 10.0), (6, 40.0)]);
+        bvh.ease_to_pose(&target, 4).unwrap();
+        let frames = &bvh.motion.frames;
+        assert_eq!(frames[5], target);
+        assert_eq!(frames[1], frame(&[]));
+        for column in [0, 6].iter() {
+            let scale = target[*column];
+            let values: Vec<f64> = frames[1..].iter().map(|frame| frame[*column] / scale).collect();
+            assert!(values.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", values);
+            assert!((values[2] - 0.5).abs() < 1e-6);
+        }
+        assert!(bvh.ease_to_pose(&[0.0; 3], 4).is_err());
+    }
+
+    #[test]
+    fn commas_between_frame_values_are_lenient_only() {
+        let values: Vec<String> = (0..CHANNELS).map(|column| format!("{}.5", column)).collect();
+        let input = source(&format!("Frames: 1\nFrame Time: 0.1\n{}\n", values.join(", ")));
+        assert!(parse(&input).is_err());
+        let options = ParseOptions {
+            lenient: true,
+            ..ParseOptions::default()
+        };
+        let bvh = parse_with_options(&input, &options).unwrap();
+        let expected: Vec<f64> = (0..CHANNELS).map(|column| column as f64 + 0.5).collect();
+        assert_eq!(bvh.motion.frames, vec![expected.clone()]);
+        let two_frames = source(&format!("Frames: 2\nFrame Time: 0.1\n{0}\n{0}\n", values.join(",")));
+        assert_eq!(parse_with_options(&two_frames, &options).unwrap().motion.frames, vec![expected.clone(), expected]);
+
+        let decimal_commas: Vec<String> = (0..CHANNELS).map(|column| format!("{},5", column)).collect();
+        let decimal_commas_input = source(&format!("Frames: 1\nFrame Time: 0.1\n{}\n", decimal_commas.join(" ")));
+        assert!(parse_with_options(&decimal_commas_input, &options).is_err());
+        let doubled_input = source(&format!("Frames: 1\nFrame Time: 0.1\n{}\n", decimal_commas[..CHANNELS / 2].join(" ")));
+        assert!(parse_with_options(&doubled_input, &options).is_err());
+    }
+
+    #[test]
+    fn orientation_stability_averages_turn_per_frame() {
+        let bvh = clip((0..4).map(|index| frame(&[(6, index as f64 * 10.0)])).collect());
+        let stability = bvh.orientation_stability();
+        assert_eq!(stability.len(), 5);
+        assert_eq!(stability[0].0, "Hips");
+        assert!(stability[0].1.abs() < 1e-6);
+        assert_eq!(stability[1].0, "LeftArm");
+        assert!((stability[1].1 - 10.0).abs() < 1e-6);
+        assert!(clip(vec![frame(&[(6, 10.0)])]).orientation_stability().iter().all(|&(_, angle)| angle == 0.0));
+    }
+
+    #[test]
+    fn frame_from_rotations_fills_rotation_channels() {
+        let mut rotations = HashMap::new();
+        rotations.insert("Hips".to_string(), [4.0, 5.0, 6.0]);
+        rotations.insert("LeftArm".to_string(), [1.0, 2.0, 3.0]);
+        rotations.insert("Tail".to_string(), [7.0, 8.0, 9.0]);
+        let frame_values = hierarchy().frame_from_rotations(&rotations);
+        assert_eq!(frame_values, frame(&[(3, 4.0), (4, 5.0), (5, 6.0), (6, 1.0), (7, 2.0), (8, 3.0)]));
+    }
+
+    #[test]
+    fn rotation_budget_favours_channels_with_larger_ranges() {
+        let bvh = clip(vec![frame(&[]), frame(&[(6, 30.0), (10, 10.0)])]);
+        let budget = bvh.rotation_budget(16);
+        assert_eq!(budget.len(), 15);
+        assert_eq!(budget.iter().map(|&(_, _, bits)| bits).sum::<usize>(), 16);
+        let bits = |name: &str, channel: Channel| budget.iter().find(|entry| entry.0 == name && entry.1 == channel).unwrap().2;
+        assert_eq!(bits("LeftArm", Channel::ZRotation), 12);
+        assert_eq!(bits("LeftHand", Channel::XRotation), 4);
+        assert_eq!(bits("Hips", Channel::ZRotation), 0);
+        let still = clip(vec![frame(&[])]).rotation_budget(30);
+        assert!(still.iter().all(|&(_, _, bits)| bits == 2));
+    }
+
+    #[test]
+    fn blend_offsets_interpolates_matching_hierarchies() {
+        let a = hierarchy();
+        let mut b = hierarchy();
+        if let JointChildren::Joints(ref mut children) = b.root.children {
+            children[0].offset = Offset {
+                x: 3.0,
+                y: 2.0,
+                z: 4.0,
+            };
+        }
+        let left_arm = |t: f64| Hierarchy::blend_offsets(&a, &b, t).unwrap().rest_positions()[1];
+        assert_eq!(left_arm(0.0), [1.0, 2.0, 0.0]);
+        assert_eq!(left_arm(1.0), [3.0, 2.0, 4.0]);
+        assert_eq!(left_arm(0.5), [2.0, 2.0, 2.0]);
+        if let JointChildren::Joints(ref mut children) = b.root.children {
+            children.pop();
+        }
+        assert!(Hierarchy::blend_offsets(&a, &b, 0.5).is_err());
+    }
+
+    #[test]
+    fn root_ground_path_drops_the_up_axis() {
+        let steps = [(0.0, 0.0), (1.0, 0.0), (2.0, 0.0), (2.0, 1.0), (2.0, 2.0)];
+        let bvh = clip(steps.iter().enumerate().map(|(index, &(x, z))| frame(&[(0, x), (1, index as f64 % 2.0), (2, z)])).collect());
+        assert_eq!(bvh.root_ground_path(Axis::Y), vec![[0.0, 0.0], [1.0, 0.0], [2.0, 0.0], [2.0, 1.0], [2.0, 2.0]]);
+        assert_eq!(bvh.root_ground_path(Axis::Z)[3], [2.0, 1.0]);
+        assert_eq!(bvh.root_ground_path(Axis::X)[4], [0.0, 2.0]);
+    }
+
+    #[test]
+    fn max_line_width_wraps_frames_that_still_parse() {
+        let bvh = busy_clip();
+        let options = SerializeOptions {
+            max_line_width: Some(40),
+            ..SerializeOptions::default()
+        };
+        let serialized = to_string(&bvh, &options);
+        let lines = frame_lines(&serialized);
+        assert!(lines.len() > bvh.motion.frames.len());
+        assert!(lines.iter().all(|line| line.len() <= 40), "{:?}", lines);
+        assert_eq!(parse(&serialized).unwrap().motion.frames, bvh.motion.frames);
+    }
+
+    #[test]
+    fn mean_pose_of_symmetric_clip_is_the_center() {
+        let bvh = clip([-1.0, 0.0, 1.0].iter().map(|&t| frame(&[(0, 2.0 * t), (6, 30.0 * t), (10, -15.0 * t)])).collect());
+        assert!(bvh.mean_pose().iter().all(|value| value.abs() < 1e-6), "{:?}", bvh.mean_pose());
+        let wrapped = clip(vec![frame(&[(6, 170.0)]), frame(&[(6, -170.0)])]).mean_pose();
+        assert!((wrapped[6].abs() - 180.0).abs() < 1e-6, "{:?}", wrapped);
+        assert!(clip(Vec::new()).mean_pose().is_empty());
+    }
+
+    #[test]
+    fn sanitize_replaces_non_finite_values() {
+        let mut motion = clip(vec![frame(&[(0, f64::NAN), (5, 1.5)]), frame(&[(3, f64::INFINITY), (4, f64::NEG_INFINITY)])]).motion;
+        assert_eq!(motion.sanitize(0.0), 3);
+        assert_eq!(motion.frames, vec![frame(&[(5, 1.5)]), frame(&[])]);
+        assert_eq!(motion.sanitize(0.0), 0);
+    }
+
+    #[test]
+    fn relative_phase_finds_a_known_lag() {
+        let wave = |index: f64| (index * 2.0 * ::std::f64::consts::PI / 20.0).sin() * 30.0;
+        let bvh = clip((0..40).map(|index| frame(&[(6, wave(index as f64)), (12, wave(index as f64 - 3.0))])).collect());
+        assert_eq!(bvh.relative_phase("LeftArm", "RightArm", Channel::ZRotation), Some(3.0));
+        assert_eq!(bvh.relative_phase("RightArm", "LeftArm", Channel::ZRotation), Some(-3.0));
+        assert_eq!(bvh.relative_phase("LeftArm", "Tail", Channel::ZRotation), None);
+        assert_eq!(bvh.relative_phase("LeftArm", "LeftHand", Channel::ZRotation), None);
+    }
+
+    #[test]
+    fn parse_hierarchy_only_with_options_matches_full_parse() {
+        let input = source("Frames: 1\nFrame Time: 0.1\nthis is not frame data\n")
+            .replace("OFFSET 2 0 0", "OFFSET 2 0")
+            .replace("Zrotation Xrotation Yrotation\nEnd", "rotZ rotX rotY\nEnd");
+        assert!(parse_hierarchy_only(&input).is_err());
+        let mut options = ParseOptions {
+            lenient: true,
+            ..ParseOptions::default()
+        };
+        options.channel_aliases.insert("rotX".into(), Channel::XRotation);
+        options.channel_aliases.insert("rotY".into(), Channel::YRotation);
+        options.channel_aliases.insert("rotZ".into(), Channel::ZRotation);
+        let parsed = parse_hierarchy_only_with_options(&input, &options).unwrap();
+        let expected = hierarchy();
+        assert_eq!(parsed.rest_positions(), expected.rest_positions());
+        for (joint, expected_joint) in parsed.joints().iter().zip(expected.joints().iter()) {
+            assert_eq!(joint.name, expected_joint.name);
+            assert_eq!(joint.channels, expected_joint.channels);
+        }
+    }
+
+    #[test]
+    fn most_mobile_joint_picks_the_waving_hand() {
+        let bvh = clip([-30.0, 0.0, 30.0, 0.0].iter().map(|&angle| frame(&[(6, angle)])).collect());
+        let (name, diagonal) = bvh.most_mobile_joint().unwrap();
+        assert_eq!(name, "LeftHand");
+        let y = 2.0 * 30.0f64.to_radians().sin();
+        let x = 2.0 - 2.0 * 30.0f64.to_radians().cos();
+        assert!((diagonal - (4.0 * y * y + x * x).sqrt()).abs() < 1e-6);
+        assert_eq!(clip(Vec::new()).most_mobile_joint(), None);
+    }
+
+    #[test]
+    fn resample_takes_resamples_every_take() {
+        let take = |count: usize, frame_time: f64| Motion {
+            num_frames: count as u32,
+            frame_time,
+            frames: (0..count).map(|index| frame(&[(0, index as f64)])).collect(),
+        };
+        let resampled = resample_takes(&[take(5, 1.0 / 30.0), take(3, 1.0 / 10.0)], 1.0 / 60.0, CHANNELS).unwrap();
+        assert!(resampled.iter().all(|motion| motion.frame_time == 1.0 / 60.0));
+        assert_eq!(resampled[0].frames.len(), 9);
+        assert_eq!(resampled[1].frames.len(), 13);
+        assert!((resampled[0].frames[3][0] - 1.5).abs() < 1e-9);
+        assert!((resampled[1].frames[3][0] - 0.5).abs() < 1e-9);
+        let mut bad = take(3, 1.0 / 10.0);
+        bad.frames[2].pop();
+        match resample_takes(&[take(5, 1.0 / 30.0), bad], 1.0 / 60.0, CHANNELS) {
+            Err(BvhError::TakeChannelMismatch { take: 1, expected: CHANNELS, found: 17 }) => {}
+            other => panic!("expected a TakeChannelMismatch error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn symmetry_plane_of_humanoid_is_x() {
+        assert_eq!(hierarchy().symmetry_plane(), Axis::X);
+        let mut rotated = hierarchy();
+        if let JointChildren::Joints(ref mut children) = rotated.root.children {
+            for (arm, side) in children.iter_mut().zip([1.0, -1.0].iter()) {
+                arm.offset = Offset {
+                    x: 0.0,
+                    y: 2.0,
+                    z: *side,
+                };
+                if let JointChildren::Joints(ref mut hands) = arm.children {
+                    hands[0].offset = Offset {
+                        x: 0.0,
+                        y: 0.0,
+                        z: 2.0 * side,
+                    };
+                }
+            }
+        }
+        assert_eq!(rotated.symmetry_plane(), Axis::Z);
+    }
+
+    #[test]
+    fn timestamp_comments_mark_each_frame_and_are_skipped_when_parsing() {
+        let bvh = busy_clip();
+        let options = SerializeOptions {
+            timestamp_comments: true,
+            ..SerializeOptions::default()
+        };
+        let serialized = to_string(&bvh, &options);
+        let lines = frame_lines(&serialized);
+        assert_eq!(lines.len(), bvh.motion.frames.len());
+        for (index, line) in lines.iter().enumerate() {
+            assert!(line.ends_with(&format!(" # t={}", index as f64 * FRAME_TIME)), "{}", line);
+        }
+        let parsed = parse(&serialized).unwrap();
+        assert_eq!(parsed.motion.frames, bvh.motion.frames);
+        assert_eq!(to_string(&parsed, &SerializeOptions::default()), to_string(&bvh, &SerializeOptions::default()));
+    }
+
+    #[test]
+    fn all_local_rotation_matrices_match_local_matrix() {
+        let bvh = busy_clip();
+        let rotations = bvh.all_local_rotation_matrices();
+        let joints = bvh.hierarchy.joints();
+        let channel_offsets = bvh.hierarchy.channel_offsets();
+        assert_eq!(rotations.len(), bvh.motion.frames.len());
+        for (frame, frame_rotations) in bvh.motion.frames.iter().zip(rotations.iter()) {
+            assert_eq!(frame_rotations.len(), joints.len());
+            for ((joint, &channel_offset), rotation) in joints.iter().zip(channel_offsets.iter()).zip(frame_rotations.iter()) {
+                let expected = math::matrix4_rotation(&joint.local_matrix(&frame[channel_offset..channel_offset + joint.channels.len()]));
+                for row in 0..3 {
+                    assert!(close3(&rotation[row], &expected[row]));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn check_roundtrip_passes_exact_values_and_rejects_nan() {
+        assert!(busy_clip().check_roundtrip().is_ok());
+        assert!(clip(vec![frame(&[(0, 0.1 + 0.2), (6, 1.0 / 3.0), (7, -1e-300), (8, 1e300)])]).check_roundtrip().is_ok());
+        assert!(clip(vec![frame(&[(6, f64::NAN)])]).check_roundtrip().is_err());
+    }
+
+    #[test]
+    fn foot_slide_scores_sliding_planted_foot_higher() {
+        let planted = clip(vec![frame(&[]); 5]);
+        let sliding = clip((0..5).map(|index| frame(&[(0, index as f64 * 0.1)])).collect());
+        assert!(planted.foot_slide("LeftHand", 0.01).abs() < 1e-9);
+        assert!((sliding.foot_slide("LeftHand", 0.01) - 0.4).abs() < 1e-9);
+        let lifted = clip((0..5).map(|index| frame(&[(0, index as f64 * 0.1), (1, (index % 2) as f64)])).collect());
+        assert!(lifted.foot_slide("LeftHand", 0.01).abs() < 1e-9);
+        assert_eq!(sliding.foot_slide("Tail", 0.01), 0.0);
+    }
+
+    #[test]
+    fn channels_before_offset_parse_the_same() {
+        let swapped = source("Frames: 1\nFrame Time: 0.1\n0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0\n")
+            .replace("OFFSET 2 0 0\nCHANNELS 3 Zrotation Xrotation Yrotation", "CHANNELS 3 Zrotation Xrotation Yrotation\nOFFSET 2 0 0");
+        assert!(swapped.contains("Yrotation\nOFFSET 2 0 0"));
+        let parsed = parse(&swapped).unwrap().hierarchy;
+        let expected = hierarchy();
+        let left_hand = &parsed.joints()[2];
+        assert_eq!(left_hand.name, "LeftHand");
+        assert_eq!(left_hand.offset.to_array(), expected.joints()[2].offset.to_array());
+        assert_eq!(left_hand.channels, expected.joints()[2].channels);
+        assert_eq!(parsed.rest_positions(), expected.rest_positions());
+    }
+
+    #[test]
+    fn rotation_deltas_from_bind_are_identity_for_still_joints() {
+        let bvh = clip((0..3).map(|index| frame(&[(6, index as f64 * 20.0)])).collect());
+        let deltas = bvh.rotation_deltas_from_bind();
+        assert_eq!(deltas.len(), 5);
+        for (name, rotations) in deltas.iter() {
+            assert_eq!(rotations.len(), 3);
+            if name != "LeftArm" {
+                assert!(rotations.iter().all(|rotation| *rotation == [0.0, 0.0, 0.0, 1.0]), "{}: {:?}", name, rotations);
+            }
+        }
+        let half_angle = 20.0f64.to_radians() / 2.0;
+        let left_arm = &deltas[1].1[1];
+        assert!(close3(&[left_arm[0], left_arm[1], left_arm[2]], &[0.0, 0.0, half_angle.sin()]));
+        assert!((left_arm[3] - half_angle.cos()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn complexity_score_counts_distinct_poses() {
+        let poses = [frame(&[(6, 30.0)]), frame(&[(6, -30.0), (0, 1.0)])];
+        let cycling = |length: usize| clip((0..length).map(|index| poses[index % 2].clone()).collect());
+        assert_eq!(cycling(4).complexity_score(1e-6), 2);
+        assert_eq!(cycling(400).complexity_score(1e-6), 2);
+        assert_eq!(busy_clip().complexity_score(1e-6), 4);
+        assert_eq!(clip(Vec::new()).complexity_score(1e-6), 0);
+    }
+
+    #[test]
+    fn reach_to_sums_the_chain_from_the_root() {
+        let hierarchy = hierarchy();
+        assert_eq!(hierarchy.reach_to("Hips"), Some(0.0));
+        assert!((hierarchy.reach_to("LeftArm").unwrap() - 5.0f64.sqrt()).abs() < 1e-9);
+        assert!((hierarchy.reach_to("LeftHand").unwrap() - (5.0f64.sqrt() + 2.0)).abs() < 1e-9);
+        assert_eq!(hierarchy.reach_to("Tail"), None);
+    }
+
+    #[test]
+    fn root_world_quat_follows_root_rotation() {
+        let bvh = clip(vec![frame(&[(0, 5.0)]), frame(&[(5, 90.0)]), frame(&[(5, 270.0)])]);
+        let quats = bvh.root_world_quat();
+        let half = 0.5f64.sqrt();
+        let expected = [[0.0, 0.0, 0.0, 1.0], [0.0, half, 0.0, half], [0.0, -half, 0.0, half]];
+        assert_eq!(quats.len(), expected.len());
+        for (quat, expected) in quats.iter().zip(expected.iter()) {
+            assert!((0..4).all(|i| (quat[i] - expected[i]).abs() < 1e-6), "{:?}", quat);
+        }
+    }
+
+    #[test]
+    fn thumbnail_keeps_span_and_endpoints() {
+        let bvh = clip((0..300).map(|index| frame(&[(0, index as f64)])).collect());
+        let thumbnail = bvh.thumbnail(30);
+        assert_eq!(thumbnail.motion.frames.len(), 30);
+        assert_eq!(thumbnail.motion.num_frames, 30);
+        assert_eq!(thumbnail.motion.frames.first(), bvh.motion.frames.first());
+        assert_eq!(thumbnail.motion.frames.last(), bvh.motion.frames.last());
+        assert!((thumbnail.motion.frame_time * 29.0 - FRAME_TIME * 299.0).abs() < 1e-9);
+        assert_eq!(clip(vec![frame(&[]); 10]).thumbnail(30).motion.frames.len(), 10);
+        assert_eq!(bvh.thumbnail(1).motion.frames.len(), 1);
+    }
+}
@@ -0,0 +1,282 @@
+// Matrices are row-major (`m[row][column]`) and operate on column vectors, so translations live in the last column.
+// Angles are in degrees, as they are in BVH files.
+
+pub type Vector3 = [f64; 3];
+pub type Matrix3 = [[f64; 3]; 3];
+pub type Matrix4 = [[f64; 4]; 4];
+
+pub fn matrix3_identity() -> Matrix3 {
+    [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]
+}
+
+pub fn matrix3_mul(a: &Matrix3, b: &Matrix3) -> Matrix3 {
+    let mut result = [[0.0; 3]; 3];
+    for (row, result_row) in result.iter_mut().enumerate() {
+        for (column, value) in result_row.iter_mut().enumerate() {
+            *value = (0..3).map(|i| a[row][i] * b[i][column]).sum();
+        }
+    }
+    result
+}
+
+pub fn matrix3_rotation(axis: usize, degrees: f64) -> Matrix3 {
+    let (s, c) = degrees.to_radians().sin_cos();
+    match axis {
+        0 => [[1.0, 0.0, 0.0], [0.0, c, -s], [0.0, s, c]],
+        1 => [[c, 0.0, s], [0.0, 1.0, 0.0], [-s, 0.0, c]],
+        _ => [[c, -s, 0.0], [s, c, 0.0], [0.0, 0.0, 1.0]],
+    }
+}
+
+pub fn matrix4_identity() -> Matrix4 {
+    [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]]
+}
+
+pub fn matrix4_mul(a: &Matrix4, b: &Matrix4) -> Matrix4 {
+    let mut result = [[0.0; 4]; 4];
+    for (row, result_row) in result.iter_mut().enumerate() {
+        for (column, value) in result_row.iter_mut().enumerate() {
+            *value = (0..4).map(|i| a[row][i] * b[i][column]).sum();
+        }
+    }
+    result
+}
+
+pub fn matrix4_from_parts(rotation: &Matrix3, translation: &Vector3) -> Matrix4 {
+    let mut result = matrix4_identity();
+    for row in 0..3 {
+        result[row][..3].copy_from_slice(&rotation[row]);
+        result[row][3] = translation[row];
+    }
+    result
+}
+
+pub fn matrix4_translation(matrix: &Matrix4) -> Vector3 {
+    [matrix[0][3], matrix[1][3], matrix[2][3]]
+}
+
+pub fn matrix4_rotation(matrix: &Matrix4) -> Matrix3 {
+    [
+        [matrix[0][0], matrix[0][1], matrix[0][2]],
+        [matrix[1][0], matrix[1][1], matrix[1][2]],
+        [matrix[2][0], matrix[2][1], matrix[2][2]],
+    ]
+}
+
+pub fn add(a: &Vector3, b: &Vector3) -> Vector3 {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+pub fn sub(a: &Vector3, b: &Vector3) -> Vector3 {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+pub fn scale(v: &Vector3, s: f64) -> Vector3 {
+    [v[0] * s, v[1] * s, v[2] * s]
+}
+
+pub fn length(v: &Vector3) -> f64 {
+    (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt()
+}
+
+pub fn dot(a: &Vector3, b: &Vector3) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+pub fn cross(a: &Vector3, b: &Vector3) -> Vector3 {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+pub fn normalize(v: &Vector3) -> Vector3 {
+    let length = length(v);
+    if length > 0.0 {
+        scale(v, 1.0 / length)
+    } else {
+        *v
+    }
+}
+
+pub fn matrix3_transpose(matrix: &Matrix3) -> Matrix3 {
+    let mut result = [[0.0; 3]; 3];
+    for (row, result_row) in result.iter_mut().enumerate() {
+        for (column, value) in result_row.iter_mut().enumerate() {
+            *value = matrix[column][row];
+        }
+    }
+    result
+}
+
+pub fn matrix3_mul_vector(matrix: &Matrix3, v: &Vector3) -> Vector3 {
+    [dot(&matrix[0], v), dot(&matrix[1], v), dot(&matrix[2], v)]
+}
+
+// Euler angles (in degrees) about `axes`, applied in that order, that make up `matrix`: the inverse of composing rotation channels as
+// `Joint::local_matrix` does. `axes` must be three distinct axes.
+pub fn matrix3_to_euler(matrix: &Matrix3, axes: [usize; 3]) -> [f64; 3] {
+    let (i, j, k) = (axes[0], axes[1], axes[2]);
+    let parity = if (j + 3 - i) % 3 == 1 { 1.0 } else { -1.0 };
+    let cos_second = matrix[i][i].hypot(matrix[i][j]);
+    let second = (parity * matrix[i][k]).atan2(cos_second);
+    let (first, third) = if cos_second > 1e-9 {
+        ((-parity * matrix[j][k]).atan2(matrix[k][k]), (-parity * matrix[i][j]).atan2(matrix[i][i]))
+    } else {
+        // Gimbal lock: only the sum/difference of the outer angles is defined, so put all of it in the first
+        ((parity * matrix[k][j]).atan2(matrix[j][j]), 0.0)
+    };
+    // Adding zero turns -0 into 0 so it serializes cleanly
+    [first.to_degrees() + 0.0, second.to_degrees() + 0.0, third.to_degrees() + 0.0]
+}
+
+// Smallest rotation taking the direction of `from` to the direction of `to`.
+pub fn rotation_between(from: &Vector3, to: &Vector3) -> Matrix3 {
+    let from = normalize(from);
+    let to = normalize(to);
+    let c = dot(&from, &to);
+    if c < -1.0 + 1e-12 {
+        // Opposite directions; rotate half a turn about any perpendicular axis
+        let helper = if from[0].abs() < 0.9 { [1.0, 0.0, 0.0] } else { [0.0, 1.0, 0.0] };
+        let axis = normalize(&cross(&from, &helper));
+        let mut result = [[0.0; 3]; 3];
+        for (row, result_row) in result.iter_mut().enumerate() {
+            for (column, value) in result_row.iter_mut().enumerate() {
+                *value = 2.0 * axis[row] * axis[column] - if row == column { 1.0 } else { 0.0 };
+            }
+        }
+        return result;
+    }
+    let k = cross(&from, &to);
+    let skew = [[0.0, -k[2], k[1]], [k[2], 0.0, -k[0]], [-k[1], k[0], 0.0]];
+    let skew_squared = matrix3_mul(&skew, &skew);
+    let mut result = matrix3_identity();
+    for row in 0..3 {
+        for column in 0..3 {
+            result[row][column] += skew[row][column] + skew_squared[row][column] / (1.0 + c);
+        }
+    }
+    result
+}
+
+// Rotation taking the pair of directions `from` onto `to` (TRIAD method). The first direction is matched exactly.
+pub fn rotation_from_pairs(from: (&Vector3, &Vector3), to: (&Vector3, &Vector3)) -> Matrix3 {
+    let basis = |a: &Vector3, b: &Vector3| {
+        let first = normalize(a);
+        let second = normalize(&cross(a, b));
+        let third = cross(&first, &second);
+        [first, second, third]
+    };
+    let from = basis(from.0, from.1);
+    let to = basis(to.0, to.1);
+    let mut result = [[0.0; 3]; 3];
+    for (row, result_row) in result.iter_mut().enumerate() {
+        for (column, value) in result_row.iter_mut().enumerate() {
+            *value = (0..3).map(|i| to[i][row] * from[i][column]).sum();
+        }
+    }
+    result
+}
+
+// Convex hull of `points` in counter-clockwise order (Andrew's monotone chain), without collinear points.
+pub fn convex_hull_2d(mut points: Vec<[f64; 2]>) -> Vec<[f64; 2]> {
+    points.sort_by(|a, b| a.partial_cmp(b).unwrap_or(::std::cmp::Ordering::Equal));
+    points.dedup();
+    if points.len() < 3 {
+        return points;
+    }
+    let turn = |o: &[f64; 2], a: &[f64; 2], b: &[f64; 2]| (a[0] - o[0]) * (b[1] - o[1]) - (a[1] - o[1]) * (b[0] - o[0]);
+    let half_hull = |points: &mut dyn Iterator<Item = &[f64; 2]>| {
+        let mut hull: Vec<[f64; 2]> = Vec::new();
+        for point in points {
+            while hull.len() >= 2 && turn(&hull[hull.len() - 2], &hull[hull.len() - 1], point) <= 0.0 {
+                hull.pop();
+            }
+            hull.push(*point);
+        }
+        hull.pop();
+        hull
+    };
+    let mut hull = half_hull(&mut points.iter());
+    hull.extend(half_hull(&mut points.iter().rev()));
+    hull
+}
+
+// Quaternions are stored as [x, y, z, w].
+pub type Quaternion = [f64; 4];
+
+pub fn matrix3_to_quaternion(m: &Matrix3) -> Quaternion {
+    let trace = m[0][0] + m[1][1] + m[2][2];
+    let q = if trace > 0.0 {
+        let s = (trace + 1.0).sqrt() * 2.0;
+        [(m[2][1] - m[1][2]) / s, (m[0][2] - m[2][0]) / s, (m[1][0] - m[0][1]) / s, 0.25 * s]
+    } else if m[0][0] > m[1][1] && m[0][0] > m[2][2] {
+        let s = (1.0 + m[0][0] - m[1][1] - m[2][2]).sqrt() * 2.0;
+        [0.25 * s, (m[0][1] + m[1][0]) / s, (m[0][2] + m[2][0]) / s, (m[2][1] - m[1][2]) / s]
+    } else if m[1][1] > m[2][2] {
+        let s = (1.0 + m[1][1] - m[0][0] - m[2][2]).sqrt() * 2.0;
+        [(m[0][1] + m[1][0]) / s, 0.25 * s, (m[1][2] + m[2][1]) / s, (m[0][2] - m[2][0]) / s]
+    } else {
+        let s = (1.0 + m[2][2] - m[0][0] - m[1][1]).sqrt() * 2.0;
+        [(m[0][2] + m[2][0]) / s, (m[1][2] + m[2][1]) / s, 0.25 * s, (m[1][0] - m[0][1]) / s]
+    };
+    // Keep w non-negative so equal rotations give equal quaternions
+    if q[3] < 0.0 {
+        [-q[0], -q[1], -q[2], -q[3]]
+    } else {
+        q
+    }
+}
+
+// Rotation axis scaled by the rotation angle in degrees.
+pub fn quaternion_to_rotation_vector(q: &Quaternion) -> Vector3 {
+    let q = if q[3] < 0.0 { [-q[0], -q[1], -q[2], -q[3]] } else { *q };
+    let axis = [q[0], q[1], q[2]];
+    let sin_half_angle = length(&axis);
+    if sin_half_angle < 1e-12 {
+        return [0.0; 3];
+    }
+    let angle = 2.0 * sin_half_angle.atan2(q[3]);
+    scale(&axis, angle.to_degrees() / sin_half_angle)
+}
+
+pub fn quaternion_mul(a: &Quaternion, b: &Quaternion) -> Quaternion {
+    [
+        a[3] * b[0] + a[0] * b[3] + a[1] * b[2] - a[2] * b[1],
+        a[3] * b[1] - a[0] * b[2] + a[1] * b[3] + a[2] * b[0],
+        a[3] * b[2] + a[0] * b[1] - a[1] * b[0] + a[2] * b[3],
+        a[3] * b[3] - a[0] * b[0] - a[1] * b[1] - a[2] * b[2],
+    ]
+}
+
+pub fn quaternion_conjugate(q: &Quaternion) -> Quaternion {
+    [-q[0], -q[1], -q[2], q[3]]
+}
+
+pub fn quaternion_to_matrix3(q: &Quaternion) -> Matrix3 {
+    let (x, y, z, w) = (q[0], q[1], q[2], q[3]);
+    [
+        [1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y - z * w), 2.0 * (x * z + y * w)],
+        [2.0 * (x * y + z * w), 1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z - x * w)],
+        [2.0 * (x * z - y * w), 2.0 * (y * z + x * w), 1.0 - 2.0 * (x * x + y * y)],
+    ]
+}
+
+// Spherical interpolation along the shorter arc between two unit quaternions.
+pub fn quaternion_slerp(a: &Quaternion, b: &Quaternion, t: f64) -> Quaternion {
+    let mut cos_angle = (0..4).map(|i| a[i] * b[i]).sum::<f64>();
+    let b = if cos_angle < 0.0 {
+        cos_angle = -cos_angle;
+        [-b[0], -b[1], -b[2], -b[3]]
+    } else {
+        *b
+    };
+    let (weight_a, weight_b) = if cos_angle > 1.0 - 1e-9 {
+        // Nearly equal; interpolate linearly to avoid dividing by a tiny sine
+        (1.0 - t, t)
+    } else {
+        let angle = cos_angle.acos();
+        let sin_angle = angle.sin();
+        (((1.0 - t) * angle).sin() / sin_angle, (t * angle).sin() / sin_angle)
+    };
+    let q = [a[0] * weight_a + b[0] * weight_b, a[1] * weight_a + b[1] * weight_b, a[2] * weight_a + b[2] * weight_b, a[3] * weight_a + b[3] * weight_b];
+    let norm = (0..4).map(|i| q[i] * q[i]).sum::<f64>().sqrt();
+    [q[0] / norm, q[1] / norm, q[2] / norm, q[3] / norm]
+}